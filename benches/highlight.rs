@@ -0,0 +1,94 @@
+use std::sync::Mutex;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use lumin::highlight::Highlight;
+use rayon::prelude::*;
+
+const RUST: &str = r#"
+use std::collections::HashMap;
+
+/// Counts the words in `text`.
+fn count_words(text: &str) -> HashMap<&str, usize> {
+    let mut counts = HashMap::new();
+    for word in text.split_whitespace() {
+        *counts.entry(word).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn main() {
+    let counts = count_words("the quick brown fox jumps over the lazy dog");
+    for (word, count) in &counts {
+        println!("{}: {}", word, count);
+    }
+}
+"#;
+
+const C: &str = r#"
+#include <stdio.h>
+
+struct point { int x; int y; };
+
+static int manhattan(struct point a, struct point b) {
+    int dx = a.x > b.x ? a.x - b.x : b.x - a.x;
+    int dy = a.y > b.y ? a.y - b.y : b.y - a.y;
+    return dx + dy;
+}
+
+int main(void) {
+    struct point a = { 1, 2 }, b = { 4, 6 };
+    printf("%d\n", manhattan(a, b));
+    return 0;
+}
+"#;
+
+/// Highlighting the code blocks of a site's posts, as `find_and_process` does from every rayon
+/// worker at once. Compared against each worker taking turns behind one lock.
+fn bench_highlight(c: &mut Criterion) {
+    const BLOCKS: usize = 256;
+
+    let highlight = Highlight::new().unwrap();
+    let blocks: Vec<_> = (0..BLOCKS)
+        .map(|i| match i % 2 {
+            0 => ("rust", RUST.repeat(4)),
+            _ => ("c", C.repeat(4)),
+        })
+        .collect();
+
+    let mut group = c.benchmark_group(format!(
+        "highlight,blocks={},threads={}",
+        BLOCKS,
+        rayon::current_num_threads()
+    ));
+
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            for (language, code) in &blocks {
+                black_box(highlight.highlight(language, code.as_bytes()).unwrap());
+            }
+        })
+    });
+
+    group.bench_function("parallel", |b| {
+        b.iter(|| {
+            blocks.par_iter().for_each(|(language, code)| {
+                black_box(highlight.highlight(language, code.as_bytes()).unwrap());
+            })
+        })
+    });
+
+    let lock = Mutex::new(());
+    group.bench_function("parallel_locked", |b| {
+        b.iter(|| {
+            blocks.par_iter().for_each(|(language, code)| {
+                let _guard = lock.lock().unwrap();
+                black_box(highlight.highlight(language, code.as_bytes()).unwrap());
+            })
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_highlight);
+criterion_main!(benches);
@@ -1,6 +1,14 @@
-use std::{error::Error, io::Write, path::Path};
-
-use criterion::{criterion_group, criterion_main, Criterion};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use lumin::compress::Level;
+use lumin::processors::StaticProcessor;
 use lumin::store::{find_and_process, EXTENSIONS};
 use rand::{seq::IteratorRandom, Rng, SeedableRng};
 
@@ -24,7 +32,7 @@ impl TreeGenerator {
 
     fn random_buffer(&mut self) -> Vec<u8> {
         let sz = *self.file_sizes.iter().choose(&mut self.rng).unwrap_or(&1);
-        std::iter::repeat(0).take(sz).collect()
+        vec![0; sz]
     }
 
     fn generate_inner(&mut self, path: &Path, current_depth: usize) -> Result<(), Box<dyn Error>> {
@@ -34,7 +42,7 @@ impl TreeGenerator {
 
         for i in 0..self.matching_files {
             let mut path = path.join(self.generate_filename());
-            path.set_extension(EXTENSIONS[i as usize % EXTENSIONS.len()]);
+            path.set_extension(EXTENSIONS[i % EXTENSIONS.len()]);
 
             let mut f = std::fs::File::create(path)?;
             let buf = self.random_buffer();
@@ -85,7 +93,7 @@ fn bench_find_and_process(c: &mut Criterion) {
 
                 gen.generate(&tmp).unwrap();
 
-                b.iter(|| find_and_process(&tmp));
+                b.iter(|| find_and_process(&tmp, &[&StaticProcessor::default()], Level::Best));
 
                 std::fs::remove_dir_all(&tmp).unwrap();
             },
@@ -93,5 +101,66 @@ fn bench_find_and_process(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, bench_find_and_process);
+/// Many threads reading from the store at once, as when serving lots of requests. Compared
+/// against copying each resource out from behind a mutex.
+fn bench_concurrent_reads(c: &mut Criterion) {
+    const THREADS: usize = 8;
+
+    let mut gen = TreeGenerator {
+        rng: rand::rngs::StdRng::seed_from_u64(0),
+        dirs: 2,
+        matching_files: 10,
+        extra_files: 0,
+        max_depth: 2,
+        file_sizes: &[1024, 64 * 1024, 1024 * 1024],
+    };
+
+    let tmp = std::env::temp_dir().join(gen.generate_filename());
+    std::fs::create_dir(&tmp).unwrap();
+    gen.generate(&tmp).unwrap();
+
+    let store = find_and_process(&tmp, &[&StaticProcessor::default()], Level::Best).unwrap();
+    let urls: Vec<_> = store.dependencies().into_keys().collect();
+    let copied: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::new(Mutex::new(
+        urls.iter()
+            .map(|url| (url.clone(), std::fs::read(tmp.join(url)).unwrap()))
+            .collect(),
+    ));
+
+    let mut group = c.benchmark_group(format!("concurrent_reads,threads={}", THREADS));
+
+    group.bench_function("store", |b| {
+        b.iter(|| {
+            std::thread::scope(|s| {
+                for _ in 0..THREADS {
+                    s.spawn(|| {
+                        for url in &urls {
+                            black_box(store.get(url));
+                        }
+                    });
+                }
+            })
+        })
+    });
+
+    group.bench_function("mutex_clone", |b| {
+        b.iter(|| {
+            std::thread::scope(|s| {
+                for _ in 0..THREADS {
+                    s.spawn(|| {
+                        for url in &urls {
+                            black_box(copied.lock().unwrap().get(url).cloned());
+                        }
+                    });
+                }
+            })
+        })
+    });
+
+    group.finish();
+
+    std::fs::remove_dir_all(&tmp).unwrap();
+}
+
+criterion_group!(benches, bench_find_and_process, bench_concurrent_reads);
 criterion_main!(benches);
@@ -9,7 +9,7 @@ fn build_lang(lang: &str, mut dir: PathBuf) -> Result<(), Box<dyn Error>> {
     for entry in std::fs::read_dir(&dir)? {
         let entry = entry?;
         if entry.path().extension().map(|e| e == "c").unwrap_or(false) {
-            build.file(&entry.path());
+            build.file(entry.path());
         }
     }
 
@@ -83,28 +83,192 @@ mod {lang} {{
     Ok(())
 }
 
-fn lang(lang: &str) -> Result<(), Box<dyn Error>> {
+/// Where a language's grammar comes from.
+enum Source {
+    /// A submodule of `third_party`, compiled here.
+    Vendored,
+    /// A crate, through the expressions for its `Language` and for its highlights, injections
+    /// and locals queries.
+    Crate {
+        language: &'static str,
+        queries: [&'static str; 3],
+    },
+}
+
+/// Every language lumin can be built with. Each has a `lang-<name>` feature.
+const LANGS: &[(&str, Source)] = &[
+    (
+        "bash",
+        Source::Crate {
+            language: "tree_sitter_bash::language()",
+            queries: ["tree_sitter_bash::HIGHLIGHT_QUERY", r#""""#, r#""""#],
+        },
+    ),
+    ("c", Source::Vendored),
+    (
+        "cpp",
+        Source::Crate {
+            language: "tree_sitter_cpp::language()",
+            // Like the C++ grammar, its queries build on C's
+            queries: [
+                "&[tree_sitter_cpp::HIGHLIGHT_QUERY, C_HIGHLIGHT_QUERY].concat()",
+                r#""""#,
+                r#""""#,
+            ],
+        },
+    ),
+    (
+        "css",
+        Source::Crate {
+            language: "tree_sitter_css::language()",
+            queries: ["tree_sitter_css::HIGHLIGHTS_QUERY", r#""""#, r#""""#],
+        },
+    ),
+    (
+        "go",
+        Source::Crate {
+            language: "tree_sitter_go::language()",
+            queries: ["tree_sitter_go::HIGHLIGHT_QUERY", r#""""#, r#""""#],
+        },
+    ),
+    ("haskell", Source::Vendored),
+    (
+        "html",
+        Source::Crate {
+            language: "tree_sitter_html::language()",
+            queries: [
+                "tree_sitter_html::HIGHLIGHTS_QUERY",
+                "tree_sitter_html::INJECTIONS_QUERY",
+                r#""""#,
+            ],
+        },
+    ),
+    (
+        "java",
+        Source::Crate {
+            language: "tree_sitter_java::language()",
+            queries: ["tree_sitter_java::HIGHLIGHT_QUERY", r#""""#, r#""""#],
+        },
+    ),
+    (
+        "javascript",
+        Source::Crate {
+            language: "tree_sitter_javascript::language()",
+            queries: [
+                "&[tree_sitter_javascript::HIGHLIGHT_QUERY, tree_sitter_javascript::JSX_HIGHLIGHT_QUERY].concat()",
+                "tree_sitter_javascript::INJECTION_QUERY",
+                "tree_sitter_javascript::LOCALS_QUERY",
+            ],
+        },
+    ),
+    (
+        "json",
+        Source::Crate {
+            language: "tree_sitter_json::language()",
+            queries: ["tree_sitter_json::HIGHLIGHT_QUERY", r#""""#, r#""""#],
+        },
+    ),
+    (
+        "python",
+        Source::Crate {
+            language: "tree_sitter_python::language()",
+            queries: ["tree_sitter_python::HIGHLIGHT_QUERY", r#""""#, r#""""#],
+        },
+    ),
+    (
+        "ruby",
+        Source::Crate {
+            language: "tree_sitter_ruby::language()",
+            queries: [
+                "tree_sitter_ruby::HIGHLIGHT_QUERY",
+                r#""""#,
+                "tree_sitter_ruby::LOCALS_QUERY",
+            ],
+        },
+    ),
+    ("rust", Source::Vendored),
+    (
+        "toml",
+        Source::Crate {
+            language: "tree_sitter_toml::language()",
+            queries: ["tree_sitter_toml::HIGHLIGHT_QUERY", r#""""#, r#""""#],
+        },
+    ),
+    (
+        "typescript",
+        Source::Crate {
+            language: "tree_sitter_typescript::language_typescript()",
+            // TypeScript's queries only add to JavaScript's
+            queries: [
+                "&[tree_sitter_typescript::HIGHLIGHT_QUERY, tree_sitter_javascript::HIGHLIGHT_QUERY].concat()",
+                "tree_sitter_javascript::INJECTION_QUERY",
+                "&[tree_sitter_typescript::LOCALS_QUERY, tree_sitter_javascript::LOCALS_QUERY].concat()",
+            ],
+        },
+    ),
+    ("zig", Source::Vendored),
+];
+
+fn third_party(lang: &str) -> PathBuf {
     let module = format!("tree-sitter-{}", lang);
-    let dir: PathBuf = ["third_party", &module].iter().collect();
+    ["third_party", &module].iter().collect()
+}
+
+fn lang(lang: &str) -> Result<(), Box<dyn Error>> {
+    let dir = third_party(lang);
 
     build_lang(lang, dir.clone())?;
     write_lang(lang, dir)?;
     Ok(())
 }
 
+fn write_crate_lang(lang: &str, language: &str, queries: &[&str; 3]) -> Result<(), Box<dyn Error>> {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let filepath = lang_filepath(&out_dir, lang);
+
+    let contents = format!(
+        r#"
+mod {lang} {{
+    use super::*;
+
+    pub fn get_config(highlight_names: &[&str]) -> Result<HighlightConfiguration, Box<dyn Error>> {{
+        let mut config = HighlightConfiguration::new({language}, {queries})?;
+        config.configure(highlight_names);
+        Ok(config)
+    }}
+}}
+    "#,
+        lang = lang,
+        language = language,
+        queries = queries.join(", ")
+    );
+
+    std::fs::write(filepath, contents)?;
+
+    Ok(())
+}
+
+/// Whether the `lang-<name>` feature is enabled for `lang`.
+fn enabled(lang: &str) -> bool {
+    std::env::var_os(format!("CARGO_FEATURE_LANG_{}", lang.to_uppercase())).is_some()
+}
+
 fn main() {
     let out_dir = std::env::var("OUT_DIR").unwrap();
 
-    let langs = &["zig", "c", "rust", "haskell"];
+    let langs: Vec<_> = LANGS.iter().filter(|(l, _)| enabled(l)).collect();
 
     langs
         .iter()
-        .try_for_each(|l| lang(l))
+        .try_for_each(|(l, source)| match source {
+            Source::Vendored => lang(l),
+            Source::Crate { language, queries } => write_crate_lang(l, language, queries),
+        })
         .expect("could not build");
 
     let includes: Vec<_> = langs
         .iter()
-        .map(|l| {
+        .map(|(l, _)| {
             format!(
                 r#"include!("{}");"#,
                 lang_filepath(&out_dir, l).to_string_lossy()
@@ -115,26 +279,43 @@ fn main() {
 
     let inserts: Vec<_> = langs
         .iter()
-        .map(|l| format!(r#"hm.insert("{0}", {0}::get_config(highlight_names)?);"#, l))
+        .map(|(l, _)| format!(r#"hm.insert("{0}", {0}::get_config(highlight_names)?);"#, l))
         .collect();
     let inserts = inserts.join("\n");
 
+    // C++'s queries build on the C ones, which are vendored whether or not C is enabled
+    let c_highlights = if enabled("cpp") {
+        let path = third_party("c").join("queries/highlights.scm");
+        let path = path.canonicalize().expect("could not find C's queries");
+        format!(
+            r#"const C_HIGHLIGHT_QUERY: &str = include_str!("{}");"#,
+            path.to_string_lossy()
+        )
+    } else {
+        String::new()
+    };
+
     let contents = format!(
         r#"
 use std::collections::HashMap;
 use std::error::Error;
+#[allow(unused_imports)]
 use tree_sitter::Language;
+#[allow(unused_imports)]
 use tree_sitter_highlight::HighlightConfiguration;
 
 {}
 
+{}
+
+#[allow(unused_mut, unused_variables)]
 pub fn get_configs(highlight_names: &[&'static str]) -> Result<HashMap<&'static str, HighlightConfiguration>, Box<dyn Error>> {{
     let mut hm = HashMap::new();
     {}
     Ok(hm)
 }}
 "#,
-        includes, inserts
+        c_highlights, includes, inserts
     );
 
     let mod_path: PathBuf = [&out_dir, "generated_tree_sitter.rs"].iter().collect();
@@ -22,7 +22,7 @@ in {
       wantedBy = ["multi-user.target"];
 
       serviceConfig = {
-        ExecStart = "${pkg}/bin/lumin ${cfg.site}";
+        ExecStart = "${pkg}/bin/lumin serve ${cfg.site}";
         ProtectHome = "read-only";
         Restart = "on-failure";
         Type = "exec";
@@ -0,0 +1,242 @@
+use liquid_core::{
+    parser::{FilterArguments, ParameterReflection},
+    Filter, FilterReflection, ParseFilter, Runtime, Value, ValueView,
+};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+};
+
+/// Returns `url` with a hash of `contents` before its extension, e.g. `css/style.css` becomes
+/// `css/style.0123456789abcdef.css`.
+pub fn fingerprint(url: &str, mut contents: impl std::io::Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut contents, &mut hasher)?;
+    let hash: String = hasher.finalize()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    let (dir, file) = url.rsplit_once('/').unwrap_or(("", url));
+    let file = match file.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}.{}.{}", stem, hash, ext),
+        _ => format!("{}.{}", file, hash),
+    };
+
+    Ok(match dir {
+        "" => file,
+        dir => format!("{}/{}", dir, file),
+    })
+}
+
+/// Replaces the URL in every `href` and `src` attribute in `html` with what `replace` returns
+/// for it, leaving those it returns `None` for as they are.
+pub fn replace_references(html: &str, mut replace: impl FnMut(&str) -> Option<String>) -> String {
+    static REFERENCE: OnceLock<Regex> = OnceLock::new();
+    let reference =
+        REFERENCE.get_or_init(|| Regex::new(r#"\b(href|src)=("[^"]*"|'[^']*')"#).unwrap());
+
+    reference
+        .replace_all(html, |c: &regex::Captures| {
+            let quoted = &c[2];
+            let (quote, url) = (&quoted[..1], &quoted[1..quoted.len() - 1]);
+            match replace(url) {
+                Some(new) => format!("{}={}{}{}", &c[1], quote, new, quote),
+                None => c[0].to_owned(),
+            }
+        })
+        .into_owned()
+}
+
+/// The URLs of fingerprinted assets, shared between the processors that produce them and
+/// those that refer to them.
+#[derive(Clone, Default)]
+pub struct Assets {
+    /// Original URL to fingerprinted URL and the file it came from
+    urls: Arc<RwLock<HashMap<String, (String, PathBuf)>>>,
+}
+
+impl std::fmt::Debug for Assets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Assets")
+            .field("count", &self.urls.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl Assets {
+    pub fn insert(&self, url: String, fingerprinted: String, path: PathBuf) {
+        self.urls
+            .write()
+            .unwrap()
+            .insert(url, (fingerprinted, path));
+    }
+
+    /// Forgets the assets from `path`, or from files under `path` if it is a directory.
+    pub fn remove(&self, path: &Path) {
+        let mut handle = self.urls.write().unwrap();
+        handle.retain(|_, (_, source)| !source.starts_with(path));
+    }
+
+    /// Returns the fingerprinted version of `url`, which is relative to the site.
+    pub fn get(&self, url: &str) -> Option<String> {
+        let handle = self.urls.read().unwrap();
+        handle
+            .get(url)
+            .map(|(fingerprinted, _)| fingerprinted.clone())
+    }
+
+    /// Returns every fingerprinted asset's original and fingerprinted URLs, along with the file it
+    /// came from.
+    pub fn all(&self) -> Vec<(String, String, PathBuf)> {
+        let handle = self.urls.read().unwrap();
+        handle
+            .iter()
+            .map(|(url, (fingerprinted, path))| (url.clone(), fingerprinted.clone(), path.clone()))
+            .collect()
+    }
+
+    /// Returns the files the assets came from. Anything referring to the assets has to be
+    /// rebuilt when these change.
+    pub fn sources(&self) -> Vec<PathBuf> {
+        let handle = self.urls.read().unwrap();
+        let mut sources: Vec<_> = handle.values().map(|(_, path)| path.clone()).collect();
+        sources.sort();
+        sources
+    }
+
+    /// Resolves `reference`, as found in a page at `page_url`, and returns it pointing at the
+    /// fingerprinted asset. Both URLs are relative to the site, while `reference` may be
+    /// absolute or relative to the page.
+    fn resolve(&self, page_url: &str, reference: &str) -> Option<String> {
+        if reference.contains("://") || reference.starts_with("//") {
+            return None;
+        }
+
+        let end = reference.find(['?', '#']).unwrap_or(reference.len());
+        let (path, rest) = reference.split_at(end);
+        if path.is_empty() || path.contains(':') {
+            return None;
+        }
+
+        let joined = match path.strip_prefix('/') {
+            Some(path) => path.to_owned(),
+            None => match page_url.rsplit_once('/') {
+                Some((dir, _)) => format!("{}/{}", dir, path),
+                None => path.to_owned(),
+            },
+        };
+
+        let mut segments: Vec<&str> = Vec::new();
+        for segment in joined.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                segment => segments.push(segment),
+            }
+        }
+
+        let fingerprinted = self.get(&segments.join("/"))?;
+
+        // Only the file name changes, so swapping it keeps the reference in the same form
+        let file = fingerprinted.rsplit('/').next().unwrap();
+        let dir = path.rsplit_once('/').map(|(dir, _)| dir);
+        Some(match dir {
+            Some(dir) => format!("{}/{}{}", dir, file, rest),
+            None => format!("{}{}", file, rest),
+        })
+    }
+
+    /// Points every `href` and `src` attribute in `html` that refers to a fingerprinted asset at
+    /// its new URL. `page_url` is where the page is served from, relative to the site.
+    pub fn rewrite(&self, page_url: &str, html: &str) -> String {
+        if self.urls.read().unwrap().is_empty() {
+            return html.to_owned();
+        }
+
+        replace_references(html, |reference| self.resolve(page_url, reference))
+    }
+}
+
+/// `{{ "/css/style.css" | asset_url }}` gives the fingerprinted URL of an asset, or the URL as
+/// it is if the asset isn't fingerprinted. URLs are relative to the root of the site, with or
+/// without a leading slash.
+#[derive(Debug, Clone)]
+pub struct AssetUrl {
+    assets: Assets,
+}
+
+impl AssetUrl {
+    pub fn new(assets: Assets) -> Self {
+        Self { assets }
+    }
+}
+
+impl FilterReflection for AssetUrl {
+    fn name(&self) -> &str {
+        "asset_url"
+    }
+
+    fn description(&self) -> &str {
+        "Returns the fingerprinted URL of an asset."
+    }
+
+    fn positional_parameters(&self) -> &'static [ParameterReflection] {
+        &[]
+    }
+
+    fn keyword_parameters(&self) -> &'static [ParameterReflection] {
+        &[]
+    }
+}
+
+impl ParseFilter for AssetUrl {
+    fn parse(&self, mut arguments: FilterArguments) -> liquid_core::Result<Box<dyn Filter>> {
+        if arguments.positional.next().is_some() || arguments.keyword.next().is_some() {
+            return Err(liquid_core::Error::with_msg("asset_url takes no arguments"));
+        }
+
+        Ok(Box::new(AssetUrlFilter {
+            assets: self.assets.clone(),
+        }))
+    }
+
+    fn reflection(&self) -> &dyn FilterReflection {
+        self
+    }
+}
+
+#[derive(Debug)]
+struct AssetUrlFilter {
+    assets: Assets,
+}
+
+impl std::fmt::Display for AssetUrlFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("asset_url")
+    }
+}
+
+impl Filter for AssetUrlFilter {
+    fn evaluate(
+        &self,
+        input: &dyn ValueView,
+        _runtime: &dyn Runtime,
+    ) -> liquid_core::Result<Value> {
+        let url = input.to_kstr();
+        let (slash, relative) = match url.strip_prefix('/') {
+            Some(relative) => ("/", relative),
+            None => ("", url.as_str()),
+        };
+
+        Ok(match self.assets.get(relative) {
+            Some(fingerprinted) => Value::scalar(format!("{}{}", slash, fingerprinted)),
+            None => Value::scalar(url.into_owned()),
+        })
+    }
+}
@@ -0,0 +1,110 @@
+use std::{error::Error, io::Write};
+
+/// Content types other than `text/*` that are worth compressing.
+const COMPRESSIBLE_TYPES: &[&str] = &[
+    "application/atom+xml",
+    "application/javascript",
+    "application/json",
+    "application/rss+xml",
+    "application/xml",
+    "image/svg+xml",
+];
+
+/// How hard to try when compressing resources ahead of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// For rebuilds while working on the site, which should be quick
+    Fast,
+    /// The smallest output, however long it takes
+    Best,
+}
+
+/// Ways a response body can be encoded, in the order they are preferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Identity,
+}
+
+impl Encoding {
+    /// The name used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Identity => "identity",
+        }
+    }
+
+    /// The extension given to precompressed files, as nginx's `gzip_static` expects.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Brotli => Some("br"),
+            Encoding::Gzip => Some("gz"),
+            Encoding::Identity => None,
+        }
+    }
+
+    /// Picks the best of `available` that `accept_encoding` allows, falling back to sending the
+    /// contents as they are. Quality values other than zero are only used to break ties with the
+    /// server's preference.
+    pub fn negotiate(accept_encoding: &str, available: &[Encoding]) -> Encoding {
+        let mut best: Option<(f32, Encoding)> = None;
+
+        for encoding in available {
+            let quality = accept_encoding
+                .split(',')
+                .filter_map(|part| {
+                    let mut params = part.split(';');
+                    let name = params.next()?.trim();
+                    if !name.eq_ignore_ascii_case(encoding.name()) && name != "*" {
+                        return None;
+                    }
+
+                    let quality = params
+                        .filter_map(|p| p.trim().strip_prefix("q="))
+                        .find_map(|q| q.trim().parse::<f32>().ok())
+                        .unwrap_or(1.0);
+                    Some((name == "*", quality))
+                })
+                // An explicit mention of the encoding wins over a wildcard
+                .max_by(|a, b| b.0.cmp(&a.0).then(a.1.total_cmp(&b.1)))
+                .map(|(_, q)| q)
+                .unwrap_or(0.0);
+
+            if quality > 0.0 && best.map(|(q, _)| quality > q).unwrap_or(true) {
+                best = Some((quality, *encoding));
+            }
+        }
+
+        best.map(|(_, e)| e).unwrap_or(Encoding::Identity)
+    }
+}
+
+pub fn compressible(content_type: &str) -> bool {
+    content_type.starts_with("text/") || COMPRESSIBLE_TYPES.contains(&content_type)
+}
+
+pub fn gzip(contents: &[u8], level: Level) -> Result<Vec<u8>, Box<dyn Error>> {
+    let compression = match level {
+        Level::Fast => flate2::Compression::new(5),
+        Level::Best => flate2::Compression::best(),
+    };
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), compression);
+    encoder.write_all(contents)?;
+    Ok(encoder.finish()?)
+}
+
+pub fn brotli(contents: &[u8], level: Level) -> Result<Vec<u8>, Box<dyn Error>> {
+    let quality = match level {
+        Level::Fast => 5,
+        Level::Best => 11,
+    };
+    let mut buf = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut buf, 4096, quality, 22);
+        encoder.write_all(contents)?;
+    }
+    Ok(buf)
+}
@@ -0,0 +1,244 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+use crate::redirects::RedirectRule;
+use crate::store::ProcessError;
+
+pub const CONFIG_FILE: &str = "lumin.toml";
+
+/// Information about the site, given to every template as `site`.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SiteConfig {
+    pub title: String,
+    pub base_url: String,
+    pub author: String,
+
+    /// Anything else the templates want to use
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, toml::Value>,
+}
+
+/// How much of each post goes in a feed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedContent {
+    /// The whole post
+    #[default]
+    Full,
+    /// Just its description
+    Summary,
+}
+
+/// The `[feeds]` table of `lumin.toml`, choosing which of the built in feeds to generate.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedsConfig {
+    /// `atom.xml`, unless `feed_template` exists, in which case it is rendered instead
+    pub atom: bool,
+    /// `rss.xml`
+    pub rss: bool,
+    /// `feed.json`
+    pub json: bool,
+    /// How much of each post goes in the feeds
+    pub content: FeedContent,
+}
+
+impl Default for FeedsConfig {
+    fn default() -> Self {
+        Self {
+            atom: true,
+            rss: true,
+            json: true,
+            content: FeedContent::Full,
+        }
+    }
+}
+
+/// The `[highlight]` table of `lumin.toml`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlightConfig {
+    /// Other names for languages, as used after the opening fence of code blocks
+    pub aliases: BTreeMap<String, String>,
+}
+
+/// The `[sitemap]` table of `lumin.toml`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SitemapConfig {
+    /// What the URLs in the sitemap start with, if not `site.base_url`
+    pub base_url: Option<String>,
+    /// Globs of URLs to leave out of the sitemap
+    pub exclude: Vec<String>,
+}
+
+/// The `[robots]` table of `lumin.toml`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RobotsConfig {
+    /// Paths no crawler should visit, which are left out of the sitemap too
+    pub disallow: Vec<String>,
+    /// Paths particular crawlers shouldn't visit, by user agent
+    pub agents: BTreeMap<String, Vec<String>>,
+}
+
+/// The contents of `lumin.toml`. Every setting is optional.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where the site is, which the paths below have been joined onto
+    #[serde(skip)]
+    pub site_path: PathBuf,
+
+    pub address: IpAddr,
+    pub port: u16,
+
+    pub posts_dir: PathBuf,
+    pub partials_dir: PathBuf,
+    pub post_template: PathBuf,
+    pub post_list_template: PathBuf,
+    pub feed_template: PathBuf,
+    /// Renders `tags/index.html`, an overview of every tag, which is left out without it. Each
+    /// tag's own list of posts and feeds don't need it
+    pub tags_template: PathBuf,
+    /// Extra tree-sitter grammars to highlight code with, loaded at startup
+    pub grammars_dir: PathBuf,
+
+    /// How many posts to show on each page of a post list
+    pub page_size: usize,
+    /// How many posts to include in a feed
+    pub feed_length: usize,
+    pub feeds: FeedsConfig,
+
+    /// Whether to put a hash of each static asset in its URL, so they can be cached forever
+    pub fingerprint_assets: bool,
+    /// Static files bigger than this many bytes are streamed from disk instead of being kept in
+    /// memory
+    pub stream_threshold: u64,
+
+    pub site: SiteConfig,
+
+    /// `Cache-Control` for the URLs matching each glob. The first match is used.
+    #[serde(deserialize_with = "ordered")]
+    pub cache_control: Vec<(String, String)>,
+    /// Any other headers to send for the URLs matching each glob. For each header, the first
+    /// match is used.
+    #[serde(deserialize_with = "ordered")]
+    pub headers: Vec<(String, BTreeMap<String, String>)>,
+
+    /// URLs, relative to the root of the site, to redirect elsewhere or answer as gone
+    pub redirects: BTreeMap<String, RedirectRule>,
+
+    pub highlight: HighlightConfig,
+    pub sitemap: SitemapConfig,
+    pub robots: RobotsConfig,
+}
+
+/// Reads a table keeping its entries in the order they were written, as they are tried in turn.
+fn ordered<'de, D, V>(deserializer: D) -> Result<Vec<(String, V)>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    struct Visitor<V>(std::marker::PhantomData<V>);
+
+    impl<'de, V: Deserialize<'de>> de::Visitor<'de> for Visitor<V> {
+        type Value = Vec<(String, V)>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a table")
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut entries = Vec::new();
+            while let Some(entry) = map.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+
+    deserializer.deserialize_map(Visitor(std::marker::PhantomData))
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            site_path: PathBuf::new(),
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 3000,
+            posts_dir: "posts".into(),
+            partials_dir: "partials".into(),
+            post_template: "post.liquid".into(),
+            post_list_template: "post_list.liquid".into(),
+            feed_template: "feed.liquid".into(),
+            tags_template: "tags.liquid".into(),
+            grammars_dir: "grammars".into(),
+            page_size: 10,
+            feed_length: 10,
+            feeds: FeedsConfig::default(),
+            fingerprint_assets: false,
+            stream_threshold: 1024 * 1024,
+            site: SiteConfig::default(),
+            cache_control: Vec::new(),
+            headers: Vec::new(),
+            redirects: BTreeMap::new(),
+            highlight: HighlightConfig::default(),
+            sitemap: SitemapConfig::default(),
+            robots: RobotsConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `lumin.toml` from the root of the site, if there is one, then lets `overrides`
+    /// change it, as the command line does. Paths in the config are relative to the site and are
+    /// returned joined onto `site_path`, which is made canonical first so they can be compared
+    /// with the paths the file watcher reports.
+    pub fn load(
+        site_path: &Path,
+        overrides: impl FnOnce(&mut Self),
+    ) -> Result<Self, Box<dyn Error>> {
+        let site_path = &site_path
+            .canonicalize()
+            .map_err(|e| ProcessError::new(site_path, e))?;
+        let path = site_path.join(CONFIG_FILE);
+
+        let mut config: Config = if path.exists() {
+            info!(?path, "loading config");
+            let buf = std::fs::read_to_string(&path)?;
+            toml::from_str(&buf).map_err(|e| ProcessError::new(&path, e))?
+        } else {
+            Config::default()
+        };
+        overrides(&mut config);
+
+        if config.page_size == 0 {
+            return Err(ProcessError::new(&path, "page_size must be at least 1").into());
+        }
+
+        config.site_path = site_path.to_owned();
+        for relative in [
+            &mut config.posts_dir,
+            &mut config.partials_dir,
+            &mut config.post_template,
+            &mut config.post_list_template,
+            &mut config.feed_template,
+            &mut config.tags_template,
+            &mut config.grammars_dir,
+        ] {
+            // Canonical where it exists, to compare with the paths the file watcher reports
+            let path = site_path.join(&*relative);
+            *relative = path.canonicalize().unwrap_or(path);
+        }
+
+        Ok(config)
+    }
+}
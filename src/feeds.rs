@@ -0,0 +1,268 @@
+use crate::assets::replace_references;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+
+/// A feed of posts, which can be written as Atom, RSS or JSON Feed.
+pub struct Feed<'a> {
+    pub title: String,
+    pub description: &'a str,
+    pub author: &'a str,
+    /// What every URL starts with, without a trailing slash
+    pub base_url: &'a str,
+    /// The page the feed is for, relative to the site, like `tags/rust/`, or empty for the home
+    /// page
+    pub home: String,
+    /// When the posts last changed, which is when the feed was updated if it has no items
+    pub modified: DateTime<Utc>,
+    /// The items, newest first
+    pub items: Vec<Item<'a>>,
+}
+
+pub struct Item<'a> {
+    pub title: &'a str,
+    /// Relative to the site
+    pub link: &'a str,
+    pub summary: &'a str,
+    /// The post as HTML, if the whole thing is wanted, with every URL in it absolute
+    pub content: Option<String>,
+    pub published: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub tags: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    home_page_url: String,
+    feed_url: String,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    description: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<JsonAuthor<'a>>,
+    items: Vec<JsonItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonAuthor<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonItem<'a> {
+    id: String,
+    url: String,
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_html: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_text: Option<&'a str>,
+    summary: &'a str,
+    date_published: String,
+    date_modified: String,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tags: &'a [&'a str],
+}
+
+fn text(s: &str) -> std::borrow::Cow<'_, str> {
+    html_escape::encode_text(s)
+}
+
+fn attribute(s: &str) -> std::borrow::Cow<'_, str> {
+    html_escape::encode_double_quoted_attribute(s)
+}
+
+fn rfc3339(at: &DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl<'a> Feed<'a> {
+    /// The absolute URL of `path`, which is relative to the site.
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    /// When the newest item was changed, or the posts if there are none.
+    fn updated(&self) -> DateTime<Utc> {
+        self.items
+            .iter()
+            .map(|item| item.updated)
+            .max()
+            .unwrap_or(self.modified)
+    }
+
+    /// Writes the feed as Atom, which will be at `path`.
+    pub fn atom(&self, path: &str) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str(&format!("  <id>{}</id>\n", text(&self.url(&self.home))));
+        xml.push_str(&format!("  <title>{}</title>\n", text(&self.title)));
+        if !self.description.is_empty() {
+            xml.push_str(&format!(
+                "  <subtitle>{}</subtitle>\n",
+                text(self.description)
+            ));
+        }
+        xml.push_str(&format!(
+            "  <updated>{}</updated>\n",
+            rfc3339(&self.updated())
+        ));
+        xml.push_str(&format!(
+            "  <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n",
+            attribute(&self.url(&self.home))
+        ));
+        xml.push_str(&format!(
+            "  <link rel=\"self\" type=\"application/atom+xml\" href=\"{}\"/>\n",
+            attribute(&self.url(path))
+        ));
+        // Atom needs an author for every entry, which they get from here. Sites without one are
+        // turned away by `PostsProcessor::new`
+        xml.push_str(&format!(
+            "  <author><name>{}</name></author>\n",
+            text(self.author)
+        ));
+
+        for item in &self.items {
+            let url = self.url(item.link);
+            xml.push_str("  <entry>\n");
+            xml.push_str(&format!("    <id>{}</id>\n", text(&url)));
+            xml.push_str(&format!("    <title>{}</title>\n", text(item.title)));
+            xml.push_str(&format!(
+                "    <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n",
+                attribute(&url)
+            ));
+            xml.push_str(&format!(
+                "    <published>{}</published>\n",
+                rfc3339(&item.published)
+            ));
+            xml.push_str(&format!(
+                "    <updated>{}</updated>\n",
+                rfc3339(&item.updated)
+            ));
+            for tag in &item.tags {
+                xml.push_str(&format!("    <category term=\"{}\"/>\n", attribute(tag)));
+            }
+            xml.push_str(&format!("    <summary>{}</summary>\n", text(item.summary)));
+            if let Some(content) = &item.content {
+                xml.push_str(&format!(
+                    "    <content type=\"html\">{}</content>\n",
+                    text(content)
+                ));
+            }
+            xml.push_str("  </entry>\n");
+        }
+
+        xml.push_str("</feed>\n");
+        xml
+    }
+
+    /// Writes the feed as RSS 2.0, which will be at `path`.
+    pub fn rss(&self, path: &str) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str("  <channel>\n");
+        xml.push_str(&format!("    <title>{}</title>\n", text(&self.title)));
+        xml.push_str(&format!(
+            "    <link>{}</link>\n",
+            text(&self.url(&self.home))
+        ));
+        // Unlike Atom, RSS insists on a description
+        let description = match self.description {
+            "" => &self.title,
+            description => description,
+        };
+        xml.push_str(&format!(
+            "    <description>{}</description>\n",
+            text(description)
+        ));
+        xml.push_str(&format!(
+            "    <lastBuildDate>{}</lastBuildDate>\n",
+            self.updated().to_rfc2822()
+        ));
+        xml.push_str(&format!(
+            "    <atom:link rel=\"self\" type=\"application/rss+xml\" href=\"{}\"/>\n",
+            attribute(&self.url(path))
+        ));
+
+        for item in &self.items {
+            let url = self.url(item.link);
+            xml.push_str("    <item>\n");
+            xml.push_str(&format!("      <title>{}</title>\n", text(item.title)));
+            xml.push_str(&format!("      <link>{}</link>\n", text(&url)));
+            xml.push_str(&format!(
+                "      <guid isPermaLink=\"true\">{}</guid>\n",
+                text(&url)
+            ));
+            xml.push_str(&format!(
+                "      <pubDate>{}</pubDate>\n",
+                item.published.to_rfc2822()
+            ));
+            for tag in &item.tags {
+                xml.push_str(&format!("      <category>{}</category>\n", text(tag)));
+            }
+            let description = item.content.as_deref().unwrap_or(item.summary);
+            xml.push_str(&format!(
+                "      <description>{}</description>\n",
+                text(description)
+            ));
+            xml.push_str("    </item>\n");
+        }
+
+        xml.push_str("  </channel>\n");
+        xml.push_str("</rss>\n");
+        xml
+    }
+
+    /// Writes the feed as JSON Feed 1.1, which will be at `path`.
+    pub fn json(&self, path: &str) -> Result<String, serde_json::Error> {
+        let feed = JsonFeed {
+            version: "https://jsonfeed.org/version/1.1",
+            title: &self.title,
+            home_page_url: self.url(&self.home),
+            feed_url: self.url(path),
+            description: self.description,
+            authors: match self.author {
+                "" => vec![],
+                name => vec![JsonAuthor { name }],
+            },
+            items: self
+                .items
+                .iter()
+                .map(|item| JsonItem {
+                    id: self.url(item.link),
+                    url: self.url(item.link),
+                    title: item.title,
+                    // Every item needs one or the other
+                    content_html: item.content.as_deref(),
+                    content_text: match item.content {
+                        Some(_) => None,
+                        None => Some(item.summary),
+                    },
+                    summary: item.summary,
+                    date_published: rfc3339(&item.published),
+                    date_modified: rfc3339(&item.updated),
+                    tags: &item.tags,
+                })
+                .collect(),
+        };
+
+        serde_json::to_string_pretty(&feed)
+    }
+}
+
+/// Makes every `href` and `src` in `html`, from the page at `page_url`, absolute, as feed readers
+/// show it away from the site.
+pub fn absolute_urls(html: &str, base_url: &str, page_url: &str) -> String {
+    let dir = page_url.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+    replace_references(html, |url| {
+        if url.is_empty() || url.starts_with('#') || url.starts_with("//") || url.contains(':') {
+            return None;
+        }
+
+        Some(match (url.strip_prefix('/'), dir) {
+            (Some(path), _) => format!("{}/{}", base_url, path),
+            (None, "") => format!("{}/{}", base_url, url),
+            (None, dir) => format!("{}/{}/{}", base_url, dir, url),
+        })
+    })
+}
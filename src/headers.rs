@@ -0,0 +1,58 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use globset::{Glob, GlobMatcher};
+use std::error::Error;
+
+use crate::config::Config;
+
+/// Headers from `lumin.toml` to add to the responses for URLs matching a glob.
+#[derive(Debug, Default)]
+pub struct HeaderRules {
+    rules: Vec<(GlobMatcher, HeaderName, HeaderValue)>,
+}
+
+impl HeaderRules {
+    pub fn new(config: &Config) -> Result<Self, Box<dyn Error>> {
+        let mut rules = Vec::new();
+
+        for (glob, value) in &config.cache_control {
+            rules.push(Self::rule(glob, header::CACHE_CONTROL.as_str(), value)?);
+        }
+
+        for (glob, headers) in &config.headers {
+            for (name, value) in headers {
+                rules.push(Self::rule(glob, name, value)?);
+            }
+        }
+
+        Ok(Self { rules })
+    }
+
+    fn rule(
+        glob: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<(GlobMatcher, HeaderName, HeaderValue), Box<dyn Error>> {
+        let matcher = Glob::new(glob)
+            .map_err(|e| format!("invalid glob {:?}: {}", glob, e))?
+            .compile_matcher();
+        let name = HeaderName::try_from(name)
+            .map_err(|e| format!("invalid header name {:?} for {:?}: {}", name, glob, e))?;
+        let value = HeaderValue::try_from(value)
+            .map_err(|e| format!("invalid value {:?} for {} header: {}", value, name, e))?;
+        Ok((matcher, name, value))
+    }
+
+    /// Sets the headers for `url` in `headers`, replacing any the response already had. Where
+    /// several rules set the same header, the first one wins.
+    pub fn apply(&self, url: &str, headers: &mut HeaderMap) {
+        let mut set: Vec<&HeaderName> = Vec::new();
+        for (matcher, name, value) in &self.rules {
+            if set.contains(&name) || !matcher.is_match(url) {
+                continue;
+            }
+
+            headers.insert(name, value.clone());
+            set.push(name);
+        }
+    }
+}
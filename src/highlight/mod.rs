@@ -1,5 +1,11 @@
-use std::{collections::HashMap, error::Error};
-use tree_sitter_highlight::HighlightEvent;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    path::Path,
+};
+use tracing::info;
+use tree_sitter_highlight::{HighlightConfiguration, HighlightEvent};
 
 mod generated {
     include!(concat!(env!("OUT_DIR"), "/generated_tree_sitter.rs"));
@@ -37,26 +43,223 @@ const HIGHLIGHT_NAMES: &[&str] = &[
     "variable.parameter",
 ];
 
+/// Other names code blocks use for the languages, matched ignoring case. Languages lumin was
+/// built without are ignored.
+const ALIASES: &[(&str, &str)] = &[
+    ("c++", "cpp"),
+    ("golang", "go"),
+    ("h", "c"),
+    ("hs", "haskell"),
+    ("js", "javascript"),
+    ("jsx", "javascript"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("sh", "bash"),
+    ("shell", "bash"),
+    ("ts", "typescript"),
+];
+
+/// The languages of files, by their extension.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("bash", "bash"),
+    ("c", "c"),
+    ("cc", "cpp"),
+    ("cpp", "cpp"),
+    ("css", "css"),
+    ("go", "go"),
+    ("h", "c"),
+    ("hpp", "cpp"),
+    ("hs", "haskell"),
+    ("htm", "html"),
+    ("html", "html"),
+    ("java", "java"),
+    ("js", "javascript"),
+    ("json", "json"),
+    ("jsx", "javascript"),
+    ("mjs", "javascript"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("sh", "bash"),
+    ("toml", "toml"),
+    ("ts", "typescript"),
+    ("zig", "zig"),
+];
+
+thread_local! {
+    /// A highlighter keeps its parser and buffers between uses, but can only do one thing at a
+    /// time, so each thread has its own.
+    static HIGHLIGHTER: RefCell<tree_sitter_highlight::Highlighter> =
+        RefCell::new(tree_sitter_highlight::Highlighter::new());
+}
+
+/// Highlights code in the languages lumin was built with. It can be shared between threads, which
+/// can all highlight at once.
 pub struct Highlight {
-    configs: HashMap<&'static str, tree_sitter_highlight::HighlightConfiguration>,
-    highlighter: tree_sitter_highlight::Highlighter,
+    configs: HashMap<String, HighlightConfiguration>,
+    /// Lowercase alias to the language it stands for
+    aliases: HashMap<String, String>,
+    /// The grammars loaded by `load_grammars`. They're dropped after `configs`, which use them.
+    libraries: Vec<libloading::Library>,
 }
 
 impl Highlight {
     pub fn new() -> Result<Self, Box<dyn Error>> {
         Ok(Self {
-            configs: generated::get_configs(HIGHLIGHT_NAMES)?,
-            highlighter: tree_sitter_highlight::Highlighter::new(),
+            configs: generated::get_configs(HIGHLIGHT_NAMES)?
+                .into_iter()
+                .map(|(language, config)| (language.to_owned(), config))
+                .collect(),
+            aliases: ALIASES
+                .iter()
+                .map(|(alias, language)| (alias.to_string(), language.to_string()))
+                .collect(),
+            libraries: Vec::new(),
         })
     }
 
-    pub fn supported(&self, lang: &str) -> bool {
-        self.configs.contains_key(lang)
+    /// Loads the grammars in `dir`, each being a directory named after its language containing
+    /// the compiled grammar and its queries, like `python/python.so` and
+    /// `python/queries/highlights.scm`. A grammar for a built in language replaces it.
+    pub fn load_grammars(&mut self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.path());
+
+        for entry in entries {
+            let path = entry.path();
+            if !path.is_dir() {
+                continue;
+            }
+
+            let Some(language) = path.file_name().and_then(|n| n.to_str()).map(str::to_owned)
+            else {
+                continue;
+            };
+            let config = self
+                .load_grammar(&language, &path)
+                .map_err(|e| format!("couldn't load grammar from {:?}: {}", path, e))?;
+
+            info!(language, ?path, "loaded grammar");
+            self.configs.insert(language.to_lowercase(), config);
+        }
+
+        Ok(())
+    }
+
+    fn load_grammar(
+        &mut self,
+        language: &str,
+        dir: &Path,
+    ) -> Result<HighlightConfiguration, Box<dyn Error>> {
+        let mut objects = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map(|e| e == "so").unwrap_or(false) {
+                objects.push(path);
+            }
+        }
+        let object = match objects.as_slice() {
+            [object] => object,
+            [] => return Err("no .so file".into()),
+            _ => return Err("more than one .so file".into()),
+        };
+
+        let query = |filename: &str| -> Result<String, std::io::Error> {
+            let path = dir.join("queries").join(filename);
+            match path.exists() {
+                true => std::fs::read_to_string(path),
+                false => Ok(String::new()),
+            }
+        };
+        let highlights = query("highlights.scm")?;
+        if highlights.is_empty() {
+            return Err("no queries/highlights.scm".into());
+        }
+
+        // Safety: a grammar's only export is a function returning its language. It has to be a
+        // real tree-sitter grammar, but that's no different to the ones built in.
+        let symbol = format!("tree_sitter_{}", language.replace('-', "_"));
+        let grammar = unsafe {
+            let library = libloading::Library::new(object)?;
+            let function: libloading::Symbol<unsafe extern "C" fn() -> tree_sitter::Language> =
+                library.get(symbol.as_bytes())?;
+            let grammar = function();
+            self.libraries.push(library);
+            grammar
+        };
+
+        let mut config = HighlightConfiguration::new(
+            grammar,
+            &highlights,
+            &query("injections.scm")?,
+            &query("locals.scm")?,
+        )?;
+        config.configure(HIGHLIGHT_NAMES);
+        Ok(config)
+    }
+
+    /// Adds the site's own aliases, which take precedence over the built in ones.
+    pub fn add_aliases(&mut self, aliases: &BTreeMap<String, String>) -> Result<(), String> {
+        for (alias, language) in aliases {
+            let Some(language) = self.language(language).map(str::to_owned) else {
+                return Err(format!(
+                    "{} is an alias of {}, which can't be highlighted",
+                    alias, language
+                ));
+            };
+            self.aliases.insert(alias.to_lowercase(), language);
+        }
+        Ok(())
+    }
+
+    /// Finds the language `name` refers to, which may be an alias.
+    pub fn language(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        let name = self.aliases.get(&name).unwrap_or(&name);
+        self.configs
+            .get_key_value(name.as_str())
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Finds the language of the file at `path` from its extension.
+    pub fn language_for_path(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        EXTENSIONS
+            .iter()
+            .find(|(ext, _)| *ext == extension)
+            .and_then(|(_, language)| self.language(language))
+    }
+
+    /// Finds the language of a code block from its info string, everything after the opening
+    /// fence, where it comes before anything else separated by commas or spaces, as in
+    /// `rust,ignore`. Failing a language, a file name in its place is looked up by extension, as
+    /// in `main.rs`.
+    pub fn language_for_info(&self, info: &str) -> Option<&str> {
+        let name = info
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .find(|part| !part.is_empty())?;
+        self.language(name)
+            .or_else(|| self.language_for_path(Path::new(name)))
+    }
+
+    /// Highlights `code` in `language`, which may be an alias.
+    pub fn highlight(&self, language: &str, code: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let language = self
+            .language(language)
+            .ok_or_else(|| format!("can't highlight {}", language))?;
+        HIGHLIGHTER
+            .with(|highlighter| self.highlight_with(&mut highlighter.borrow_mut(), language, code))
     }
 
-    pub fn highlight(&mut self, language: &str, code: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    fn highlight_with(
+        &self,
+        highlighter: &mut tree_sitter_highlight::Highlighter,
+        language: &str,
+        code: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
         let config = &self.configs[language];
-        let highlights = self.highlighter.highlight(config, code, None, |_| None)?;
+        let highlights = highlighter.highlight(config, code, None, |_| None)?;
 
         let mut buf = Vec::with_capacity(code.len());
         for event in highlights {
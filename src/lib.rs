@@ -1,8 +1,16 @@
-use std::{error::Error, path::Path};
+use std::{collections::HashMap, error::Error, path::Path, sync::Arc};
 
+pub mod assets;
+pub mod compress;
+pub mod config;
+pub mod feeds;
+pub mod headers;
 pub mod highlight;
 pub mod processors;
+pub mod range;
+pub mod redirects;
 pub mod store;
+pub mod templates;
 
 pub trait ResourceProcessor: Send + Sync + std::fmt::Debug {
     fn matches(&self, path: &Path) -> bool;
@@ -11,4 +19,29 @@ pub trait ResourceProcessor: Send + Sync + std::fmt::Debug {
     fn flush(&self) -> Result<Vec<store::Resource>, Box<dyn Error>> {
         Ok(Vec::new())
     }
+
+    /// Forget any state kept about `path`, and anything under it if it is a directory, as it has
+    /// been deleted or is about to be processed from scratch. Files that have changed are
+    /// processed again instead, which should replace anything kept about them.
+    fn remove(&self, _path: &Path) {}
+
+    /// Recompile any templates the processor holds on to, as they or the partials have changed.
+    fn reload(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Processors run in order of stage, each stage being processed and flushed before the next
+    /// starts, so later stages can use what earlier ones collected.
+    fn stage(&self) -> u32 {
+        0
+    }
+
+    /// Produce resources describing the whole site, given every resource in it by URL. This
+    /// runs after every processor has been flushed, and again after every rebuild.
+    fn summarise(
+        &self,
+        _resources: &HashMap<String, Arc<store::Resource>>,
+    ) -> Result<Vec<store::Resource>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
 }
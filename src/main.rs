@@ -1,129 +1,475 @@
-use axum::http::{Request, StatusCode};
-use axum::response::{sse, IntoResponse};
+use axum::extract::Query;
+use axum::http::{HeaderMap, Request, StatusCode};
+use axum::response::{sse, IntoResponse, Response};
 use axum::routing::get;
-use axum::{Extension, Router};
-use clap::Parser;
+use axum::{Extension, Json, Router};
+use chrono::Utc;
+use clap::{Parser, Subcommand};
 use futures_util::stream::Stream;
-use lumin::processors::{LiquidProcessor, PostsProcessor, StaticProcessor};
-use lumin::store::{find_and_process, Store};
+use lumin::assets::Assets;
+use lumin::compress::Level;
+use lumin::config::Config;
+use lumin::headers::HeaderRules;
+use lumin::processors::{
+    HtmlProcessor, LiquidProcessor, PostsProcessor, RedirectsProcessor, SitemapProcessor,
+    StaticProcessor,
+};
+use lumin::store::{find_and_process, process_changed, ProcessError, Store};
+use lumin::templates::Templates;
 use lumin::ResourceProcessor;
 use notify_debouncer_full::notify::Watcher;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
-use std::net::SocketAddr;
-use std::path::{Path, PathBuf};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
 use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
 #[derive(Debug, Parser)]
 struct Args {
-    #[arg(help = "The site to serve")]
-    site_path: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
 
-    #[arg(short = 'd')]
-    development: bool,
+/// Settings that override those in lumin.toml. Paths are relative to the site, as they are there.
+#[derive(Debug, clap::Args)]
+struct Overrides {
+    #[arg(long, help = "Where the posts are")]
+    posts_dir: Option<PathBuf>,
+
+    #[arg(long, help = "Where the partials are")]
+    partials_dir: Option<PathBuf>,
+
+    #[arg(long, help = "The template for each post")]
+    post_template: Option<PathBuf>,
+
+    #[arg(long, help = "The template for the pages listing the posts")]
+    post_list_template: Option<PathBuf>,
+
+    #[arg(long, help = "The template for the Atom feed")]
+    feed_template: Option<PathBuf>,
+
+    #[arg(long, help = "The template for the tags overview")]
+    tags_template: Option<PathBuf>,
+
+    #[arg(long, help = "How many posts to show on each page of a post list")]
+    page_size: Option<usize>,
+
+    #[arg(long, help = "How many posts to include in a feed")]
+    feed_length: Option<usize>,
 }
 
-const DEV_RELOAD: &str = r#"
-{% if development %}
-<script type="text/javascript">
-  const eventSrc = new EventSource('/sse/update');
-  eventSrc.onmessage = (_) => {
-    location.reload();
-  };
-</script>
-{% endif %}
-"#;
+impl Overrides {
+    fn apply(self, config: &mut Config) {
+        let paths = [
+            (self.posts_dir, &mut config.posts_dir),
+            (self.partials_dir, &mut config.partials_dir),
+            (self.post_template, &mut config.post_template),
+            (self.post_list_template, &mut config.post_list_template),
+            (self.feed_template, &mut config.feed_template),
+            (self.tags_template, &mut config.tags_template),
+        ];
+        for (value, setting) in paths {
+            if let Some(value) = value {
+                *setting = value;
+            }
+        }
 
-fn create_parser(partials_dir: impl AsRef<Path>) -> Result<liquid::Parser, Box<dyn Error>> {
-    let mut ims = liquid::partials::InMemorySource::new();
+        config.page_size = self.page_size.unwrap_or(config.page_size);
+        config.feed_length = self.feed_length.unwrap_or(config.feed_length);
+    }
+}
 
-    for entry in std::fs::read_dir(partials_dir.as_ref())? {
-        let entry = entry?;
-        if entry.metadata()?.is_dir() {
-            continue;
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Serve the site, rebuilding it when files change. lumin.toml is only read at startup, so
+    /// changes to it need a restart.
+    Serve {
+        #[arg(help = "The site to serve")]
+        site_path: PathBuf,
+
+        #[arg(short = 'd')]
+        development: bool,
+
+        #[arg(long, help = "The address to listen on, overriding lumin.toml")]
+        address: Option<IpAddr>,
+
+        #[arg(
+            short = 'p',
+            long,
+            help = "The port to listen on, overriding lumin.toml"
+        )]
+        port: Option<u16>,
+
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+
+    /// Render the site into a directory of static files
+    Build {
+        #[arg(help = "The site to build")]
+        site_path: PathBuf,
+
+        #[arg(
+            short = 'o',
+            long,
+            default_value = "out",
+            help = "Where to write the site"
+        )]
+        output: PathBuf,
+
+        #[arg(long, help = "Remove the output directory before building")]
+        clean: bool,
+
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+}
+
+struct Site {
+    templates: Templates,
+    posts: PostsProcessor,
+    liquid: LiquidProcessor,
+    statics: StaticProcessor,
+    html: HtmlProcessor,
+    redirects: RedirectsProcessor,
+    sitemap: SitemapProcessor,
+    /// How hard to compress, which in development is traded for quicker rebuilds
+    compression: Level,
+    /// Changed files that are yet to be processed successfully, kept to try again with the next
+    /// change. Held while rebuilding, as the watcher and the scheduler of posts both rebuild and
+    /// each has to see the other's changes
+    pending: Mutex<Vec<PathBuf>>,
+}
+
+impl Site {
+    fn new(config: &Config, development: bool) -> Result<Self, Box<dyn Error>> {
+        let assets = Assets::default();
+        let templates = Templates::new(config, assets.clone(), development)?;
+
+        let posts = PostsProcessor::new(config, templates.clone(), development)?;
+        let liquid = LiquidProcessor::new(config, templates.clone());
+
+        let sitemap = SitemapProcessor::new(config, &posts)?;
+
+        Ok(Self {
+            templates,
+            posts,
+            liquid,
+            statics: StaticProcessor::new(config, assets.clone()),
+            html: HtmlProcessor::new(config, assets),
+            redirects: RedirectsProcessor::new(config)?,
+            sitemap,
+            compression: if development {
+                Level::Fast
+            } else {
+                Level::Best
+            },
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn processors(&self) -> Vec<&dyn ResourceProcessor> {
+        vec![
+            &self.posts,
+            &self.liquid,
+            &self.statics,
+            &self.html,
+            &self.redirects,
+            &self.sitemap,
+        ]
+    }
+
+    /// Reloads the partials and recompiles templates if any of them are in `changed`.
+    fn reload_templates(&self, changed: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+        let partials_changed = changed
+            .iter()
+            .any(|path| path.starts_with(self.templates.partials_dir()));
+        if partials_changed {
+            self.templates.reload()?;
         }
 
-        let path = entry.path();
-        debug!(?path, "found partial");
+        if partials_changed || changed.iter().any(|path| self.posts.is_template(path)) {
+            for processor in self.processors() {
+                processor.reload()?;
+            }
+        }
 
-        let short_path = path.strip_prefix(partials_dir.as_ref())?;
-        let buf = std::fs::read_to_string(&path)?;
-        ims.add(short_path.file_name().unwrap().to_string_lossy(), buf);
+        Ok(())
     }
+}
 
-    ims.add("dev_reload", DEV_RELOAD);
+/// A failed rebuild, as sent to the browser in development mode.
+#[derive(Clone, Debug, Serialize)]
+struct BuildError {
+    message: String,
+    path: Option<PathBuf>,
+}
 
-    let partials = liquid::partials::EagerCompiler::new(ims);
+impl BuildError {
+    fn new(site_path: &Path, err: &(dyn Error + 'static)) -> Self {
+        match err.downcast_ref::<ProcessError>() {
+            Some(e) => Self {
+                message: e.message.clone(),
+                path: Some(e.path.strip_prefix(site_path).unwrap_or(&e.path).to_owned()),
+            },
+            None => Self {
+                message: err.to_string(),
+                path: None,
+            },
+        }
+    }
+}
 
-    Ok(liquid::ParserBuilder::new()
-        .stdlib()
-        .partials(partials)
-        .build()?)
+#[derive(Clone, Debug)]
+enum Update {
+    Reload,
+    Failed(BuildError),
 }
 
-#[instrument(skip(store))]
+#[instrument(skip(site, store, res))]
 fn rebuild(
     path: &Path,
-    processors: &[&dyn ResourceProcessor],
+    site: &Site,
+    development: bool,
     store: Store,
+    res: notify_debouncer_full::DebounceEventResult,
 ) -> Result<(), Box<dyn Error>> {
-    let new_store = find_and_process(path, processors)?;
-    store.replace(new_store);
+    let mut pending = site.pending.lock().unwrap();
+    let processors = &site.processors();
+    let events = match res {
+        Ok(events) => events,
+        Err(errors) => {
+            errors.into_iter().for_each(|e| error!(?e, "notify error"));
+
+            // Which files were deleted isn't known, so everything is forgotten and processed again
+            for processor in processors {
+                processor.remove(path);
+            }
+            store.replace(find_and_process(path, processors, site.compression)?);
+            pending.clear();
+            return Ok(());
+        }
+    };
+
+    // Merged into the changes that are still pending, so those that failed are tried again
+    for ev in events {
+        debug!(?ev, "got notify event");
+        for path in &ev.paths {
+            if !pending.contains(path) {
+                pending.push(path.clone());
+            }
+        }
+    }
+
+    let config_path = path.join(lumin::config::CONFIG_FILE);
+    if pending.contains(&config_path) {
+        warn!(path = ?config_path, "config changed, restart to use it");
+    }
+
+    if development {
+        site.reload_templates(&pending)?;
+    }
+
+    process_changed(path, processors, &store, &pending, site.compression)?;
+    pending.clear();
     Ok(())
 }
 
+/// Returns an absolute version of `path` without requiring it to exist. The part that does
+/// exist is canonicalised, with any `..` before it resolved by dropping the previous component.
+fn absolute(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let mut normalised = PathBuf::new();
+    for component in std::env::current_dir()?.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalised.pop();
+            }
+            component => normalised.push(component),
+        }
+    }
+
+    let mut existing = normalised.as_path();
+    while !existing.exists() {
+        existing = existing.parent().unwrap_or(Path::new("/"));
+    }
+    let rest = normalised.strip_prefix(existing)?;
+    Ok(existing.canonicalize()?.join(rest))
+}
+
+#[instrument(skip(overrides))]
+fn build(
+    path: &Path,
+    output: &Path,
+    clean: bool,
+    overrides: Overrides,
+) -> Result<(), Box<dyn Error>> {
+    let site = absolute(path)?;
+    let out = absolute(output)?;
+    if out.starts_with(&site) || site.starts_with(&out) {
+        return Err(format!(
+            "output directory {:?} must not overlap the site {:?}",
+            output, path
+        )
+        .into());
+    }
+
+    let config = Config::load(path, |config| overrides.apply(config))?;
+    let site = Site::new(&config, false)?;
+    let store = find_and_process(&config.site_path, &site.processors(), site.compression)?;
+
+    if clean && output.exists() {
+        info!(?output, "removing output directory");
+        std::fs::remove_dir_all(output)?;
+    }
+
+    store.write_to(output)
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     tracing_subscriber::fmt::init();
 
     let args = Args::parse();
-    let path = args.site_path;
-
-    let partials_dir = path.join("partials");
-    let parser = create_parser(&partials_dir)?;
-
-    let s = StaticProcessor {};
-    let p = PostsProcessor::new(
-        path.join("posts"),
-        path.join("post.liquid"),
-        path.join("post_list.liquid"),
-        path.join("feed.liquid"),
-        &parser,
-        args.development,
-    )?;
-    let l = LiquidProcessor::new(partials_dir, parser, args.development);
-    let processors: &[&dyn ResourceProcessor] = &[&p, &l, &s];
-    let store = find_and_process(&path, processors)?;
+    match args.command {
+        Command::Serve {
+            site_path,
+            development,
+            address,
+            port,
+            overrides,
+        } => {
+            let config = Config::load(&site_path, |config| {
+                config.address = address.unwrap_or(config.address);
+                config.port = port.unwrap_or(config.port);
+                overrides.apply(config);
+            })?;
+            serve(config, development).await
+        }
+        Command::Build {
+            site_path,
+            output,
+            clean,
+            overrides,
+        } => build(&site_path, &output, clean, overrides),
+    }
+}
 
-    let new_store = store.clone();
-    let new_path = path.clone();
+/// Tells any browsers watching the site how a rebuild went.
+fn report(
+    site_path: &Path,
+    tx: &tokio::sync::broadcast::Sender<Update>,
+    res: Result<(), Box<dyn Error>>,
+) {
+    // The store is only updated once everything has been rebuilt, so on failure it still holds
+    // the last good version of the site
+    let update = match res {
+        Ok(()) => Update::Reload,
+        Err(e) => {
+            let e = BuildError::new(site_path, e.as_ref());
+            error!(path=?e.path, message=e.message, "rebuild failed");
+            Update::Failed(e)
+        }
+    };
 
-    let (tx, _) = tokio::sync::broadcast::channel::<()>(1);
+    // It's fine if there are no receives, so ignore the error
+    let _ = tx.send(update);
+}
+
+/// Publishes scheduled posts once their time comes. `wake` is sent to after every rebuild, as
+/// there may be newly scheduled posts or a fix for those that failed to publish.
+fn publish_scheduled(
+    path: PathBuf,
+    site: Arc<Site>,
+    store: Store,
+    tx: tokio::sync::broadcast::Sender<Update>,
+    wake: mpsc::Receiver<()>,
+) {
+    loop {
+        let (due, next) = site.posts.due(Utc::now());
+        if !due.is_empty() {
+            info!(?due, "publishing scheduled posts");
+            let res = {
+                // Left pending on failure, so the watcher publishes them along with whatever
+                // change fixes the site
+                let mut pending = site.pending.lock().unwrap();
+                for path in due {
+                    if !pending.contains(&path) {
+                        pending.push(path);
+                    }
+                }
+                process_changed(&path, &site.processors(), &store, &pending, site.compression)
+                    .map(|()| pending.clear())
+            };
+            let failed = res.is_err();
+            report(&path, &tx, res);
+
+            // Trying again straight away would fail in the same way, so wait for a change
+            if failed && wake.recv().is_err() {
+                return;
+            }
+            continue;
+        }
 
+        let res = match next {
+            Some(next) => {
+                debug!(%next, "waiting for next scheduled post");
+                wake.recv_timeout((next - Utc::now()).to_std().unwrap_or_default())
+            }
+            None => wake
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+
+        if let Err(mpsc::RecvTimeoutError::Disconnected) = res {
+            return;
+        }
+    }
+}
+
+async fn serve(config: Config, development: bool) -> Result<(), Box<dyn Error>> {
+    // Canonical, as are the paths the watcher reports under it
+    let path = config.site_path.clone();
+    let rules = Arc::new(HeaderRules::new(&config)?);
+    let site = Arc::new(Site::new(&config, development)?);
+    let store = find_and_process(&path, &site.processors(), site.compression)?;
+
+    let (tx, _) = tokio::sync::broadcast::channel::<Update>(1);
+    let (wake_tx, wake_rx) = mpsc::channel();
+
+    {
+        let path = path.clone();
+        let site = site.clone();
+        let store = store.clone();
+        let tx = tx.clone();
+        std::thread::spawn(move || publish_scheduled(path, site, store, tx, wake_rx));
+    }
+
+    let new_store = store.clone();
+    let new_path = path.clone();
     let new_tx = tx.clone();
 
     let mut debouncer = notify_debouncer_full::new_debouncer(
         Duration::from_millis(250),
         None,
         move |res: notify_debouncer_full::DebounceEventResult| {
-            let processors: &[&dyn ResourceProcessor] = &[&p, &l, &s];
             let path = new_path.clone();
             let store = new_store.clone();
             info!("files changed");
-            match res {
-                Ok(events) => events
-                    .into_iter()
-                    .for_each(|ev| debug!(?ev, "got notify event")),
-                Err(errors) => errors.into_iter().for_each(|e| error!(?e, "notify error")),
-            }
 
-            rebuild(&path, processors, store).expect("rebuild did not work");
+            report(
+                &path,
+                &new_tx,
+                rebuild(&path, &site, development, store, res),
+            );
 
-            // It's fine if there are no receives, so ignore the error
-            let _ = new_tx.send(());
+            // The scheduler may have stopped, in which case there is nothing to wake
+            let _ = wake_tx.send(());
         },
     )?;
     debouncer.watcher().watch(
@@ -135,19 +481,24 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .route("/update", get(update_sse))
         .layer(Extension(tx));
 
+    let debug = Router::new()
+        .route("/dependencies", get(dependencies))
+        .layer(Extension(path.clone()));
+
     let mut app = Router::new();
 
-    if args.development {
-        app = app.nest("/sse", sse);
+    if development {
+        app = app.nest("/sse", sse).nest("/debug", debug);
     }
 
     app = app.fallback(get(root)).layer(
         ServiceBuilder::new()
             .layer(TraceLayer::new_for_http())
-            .layer(Extension(store)),
+            .layer(Extension(store))
+            .layer(Extension(rules)),
     );
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    let addr = SocketAddr::from((config.address, config.port));
     info!("listening on {}", addr);
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
@@ -156,34 +507,131 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn not_found(store: Extension<Store>) -> impl IntoResponse {
-    let res = store.get("not_found.html").unwrap().into_response();
-    (StatusCode::NOT_FOUND, res)
+/// Responds with the not found page, with `status` being either 404 or, for URLs that have been
+/// removed on purpose, 410.
+fn not_found(
+    store: Extension<Store>,
+    rules: &HeaderRules,
+    headers: &HeaderMap,
+    status: StatusCode,
+) -> Response {
+    let mut res = store
+        .get("not_found.html")
+        .unwrap()
+        .encoded_response(headers);
+    *res.status_mut() = status;
+    rules.apply("not_found.html", res.headers_mut());
+    res
 }
 
-async fn root<T>(store: Extension<Store>, request: Request<T>) -> impl IntoResponse {
+async fn root<T>(
+    store: Extension<Store>,
+    rules: Extension<Arc<HeaderRules>>,
+    request: Request<T>,
+) -> impl IntoResponse {
+    let headers = request.headers();
     let path = request.uri().path();
-    if path == "/" {
-        return store.get("index.html").unwrap().into_response();
-    }
 
-    let path = path.trim_start_matches('/');
-    if let Some(res) = store.get(path) {
-        return res.into_response();
+    let found = if path == "/" {
+        store
+            .get("index.html")
+            .map(|res| ("index.html".to_owned(), res))
+    } else {
+        let path = path.trim_start_matches('/');
+        let index = format!("{}/index.html", path.trim_end_matches('/'));
+        store
+            .get(path)
+            .map(|res| (path.to_owned(), res))
+            .or_else(|| store.get(&index).map(|res| (index, res)))
+    };
+
+    let Some((url, res)) = found else {
+        return not_found(store, &rules, headers, StatusCode::NOT_FOUND);
+    };
+
+    if res.is_gone() {
+        return not_found(store, &rules, headers, StatusCode::GONE);
     }
 
-    let path = path.trim_end_matches('/');
-    if let Some(res) = store.get(&format!("{}/index.html", path)) {
-        return res.into_response();
+    let redirect = res.is_redirect();
+    let mut res = res.response_for(headers);
+    if !redirect {
+        rules.apply(&url, res.headers_mut());
     }
-
-    not_found(store).into_response()
+    res
 }
 
 async fn update_sse(
-    tx: Extension<tokio::sync::broadcast::Sender<()>>,
+    tx: Extension<tokio::sync::broadcast::Sender<Update>>,
 ) -> sse::Sse<impl Stream<Item = Result<sse::Event, std::convert::Infallible>>> {
     let rx = tx.0.subscribe();
-    let stream = BroadcastStream::new(rx).map(|_| Ok(sse::Event::default().data("update")));
+    let stream = BroadcastStream::new(rx).map(|update| {
+        let event = match update {
+            Ok(Update::Failed(e)) => sse::Event::default()
+                .event("build-error")
+                .json_data(e)
+                .unwrap_or_default(),
+            // If we lagged behind there has been at least one more rebuild, so reload anyway
+            Ok(Update::Reload) | Err(_) => sse::Event::default().data("update"),
+        };
+        Ok(event)
+    });
     sse::Sse::new(stream).keep_alive(sse::KeepAlive::default())
 }
+
+#[derive(Deserialize)]
+struct DependenciesQuery {
+    changed: Option<PathBuf>,
+}
+
+/// Lists the files each resource was built from or, if `changed` is given as a path relative to
+/// the site, the URLs that would be rebuilt if it changed.
+async fn dependencies(
+    store: Extension<Store>,
+    site_path: Extension<PathBuf>,
+    query: Query<DependenciesQuery>,
+) -> impl IntoResponse {
+    match &query.changed {
+        Some(changed) => Json(store.dependents(&site_path.join(changed))).into_response(),
+        None => Json(store.dependencies()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editing_a_partial_with_a_relative_site_path() {
+        // Relative to the package, where the tests are run from
+        let site_path = Path::new("target").join(format!("lumin-site-{:x}", rand::random::<u64>()));
+        std::fs::create_dir_all(site_path.join("partials")).unwrap();
+        std::fs::write(site_path.join("partials/head.liquid"), "<title>a</title>").unwrap();
+        std::fs::write(site_path.join("post.liquid"), "{{ post.content }}").unwrap();
+        std::fs::write(site_path.join("post_list.liquid"), "").unwrap();
+        std::fs::write(site_path.join("index.liquid"), r#"{% include "head.liquid" %}"#).unwrap();
+
+        let res = (|| {
+            let config = Config::load(&site_path, |_| {})?;
+            let site = Site::new(&config, true)?;
+            let processors = &site.processors();
+            let store = find_and_process(&config.site_path, processors, Level::Fast)?;
+
+            // The file watcher reports absolute paths
+            let partial = site_path.canonicalize()?.join("partials/head.liquid");
+            std::fs::write(&partial, "<title>b</title>")?;
+            let changed = [partial];
+            site.reload_templates(&changed)?;
+            process_changed(&config.site_path, processors, &store, &changed, Level::Fast)?;
+
+            let output = site_path.with_extension("out");
+            store.write_to(&output)?;
+            let index = std::fs::read_to_string(output.join("index.html"));
+            std::fs::remove_dir_all(&output)?;
+            Ok::<_, Box<dyn Error>>(index?)
+        })();
+        std::fs::remove_dir_all(&site_path).unwrap();
+
+        assert!(res.unwrap().contains("<title>b</title>"));
+    }
+}
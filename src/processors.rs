@@ -1,22 +1,56 @@
 use crate::{
+    assets::{self, Assets},
+    config::{self, Config, FeedContent, FeedsConfig, SiteConfig},
+    feeds::{self, Feed},
     highlight,
-    store::{Resource, URLPath},
+    redirects::{self, Alias, Redirect},
+    store::{OnDisk, ProcessError, Resource, URLPath},
+    templates::Templates,
     ResourceProcessor,
 };
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use markdown;
-use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     error::Error,
     path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, RwLock},
 };
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, warn};
 
-const STATIC_EXTENSIONS: &[&str] = &["css", "html", "jpg", "jpeg", "woff2", "png", "svg", "webp"];
+const STATIC_EXTENSIONS: &[&str] = &[
+    "css", "jpg", "jpeg", "woff2", "png", "svg", "webp", "mp3", "mp4", "ogg", "pdf", "webm",
+];
 
-#[derive(Debug)]
-pub struct StaticProcessor {}
+/// Returns the URL `path` is served from, relative to the site.
+fn site_url(site_path: &Path, path: &Path) -> String {
+    path.strip_prefix(site_path)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[derive(Debug, Default)]
+pub struct StaticProcessor {
+    site_path: PathBuf,
+    /// Where to keep track of the fingerprinted URLs, if fingerprinting is turned on
+    assets: Option<Assets>,
+    /// Files bigger than this are streamed from disk when requested, rather than being read now
+    stream_threshold: Option<u64>,
+}
+
+impl StaticProcessor {
+    pub fn new(config: &Config, assets: Assets) -> StaticProcessor {
+        StaticProcessor {
+            site_path: config.site_path.clone(),
+            assets: config.fingerprint_assets.then_some(assets),
+            stream_threshold: Some(config.stream_threshold),
+        }
+    }
+}
 
 impl ResourceProcessor for StaticProcessor {
     fn matches(&self, path: &Path) -> bool {
@@ -29,31 +63,348 @@ impl ResourceProcessor for StaticProcessor {
     fn process(&self, path: &Path) -> Result<Resource, Box<dyn Error>> {
         info!("statically processing");
 
+        let len = std::fs::metadata(path)?.len();
+        let mut resource = match self.stream_threshold {
+            Some(threshold) if len > threshold => {
+                debug!(len, "streaming from disk");
+                Resource {
+                    original_path: path.to_owned(),
+                    on_disk: Some(OnDisk {
+                        path: path.to_owned(),
+                        len,
+                    }),
+                    ..Default::default()
+                }
+            }
+            _ => Resource {
+                original_path: path.to_owned(),
+                contents: std::fs::read(path)?.into(),
+                ..Default::default()
+            },
+        };
+
+        let Some(assets) = &self.assets else {
+            return Ok(resource);
+        };
+
+        let url = site_url(&self.site_path, path);
+        let fingerprinted = match resource.on_disk {
+            Some(_) => assets::fingerprint(&url, std::fs::File::open(path)?)?,
+            None => assets::fingerprint(&url, &resource.contents[..])?,
+        };
+        debug!(url, fingerprinted, "fingerprinted asset");
+        assets.insert(url, fingerprinted.clone(), path.to_owned());
+
+        resource.url_path = URLPath::Absolute(fingerprinted);
+        Ok(resource)
+    }
+
+    /// Redirects the original URL of each fingerprinted asset to its new one.
+    #[instrument]
+    fn flush(&self) -> Result<Vec<Resource>, Box<dyn Error>> {
+        let Some(assets) = &self.assets else {
+            return Ok(Vec::new());
+        };
+
+        Ok(assets
+            .all()
+            .into_iter()
+            .map(|(url, fingerprinted, path)| Resource {
+                original_path: path,
+                url_path: URLPath::Absolute(url),
+                redirect: Some(Redirect::Permanent(format!("/{}", fingerprinted))),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn remove(&self, path: &Path) {
+        if let Some(assets) = &self.assets {
+            assets.remove(path);
+        }
+    }
+}
+
+/// Copies HTML files as they are, other than pointing references to fingerprinted assets at
+/// their new URLs.
+#[derive(Debug)]
+pub struct HtmlProcessor {
+    site_path: PathBuf,
+    assets: Assets,
+}
+
+impl HtmlProcessor {
+    pub fn new(config: &Config, assets: Assets) -> HtmlProcessor {
+        HtmlProcessor {
+            site_path: config.site_path.clone(),
+            assets,
+        }
+    }
+}
+
+impl ResourceProcessor for HtmlProcessor {
+    fn matches(&self, path: &Path) -> bool {
+        path.extension().map(|e| e == "html").unwrap_or(false)
+    }
+
+    #[instrument]
+    fn process(&self, path: &Path) -> Result<Resource, Box<dyn Error>> {
+        info!("html processing");
+
         let buf = std::fs::read(path)?;
+        let contents = match std::str::from_utf8(&buf) {
+            Ok(html) => self
+                .assets
+                .rewrite(&site_url(&self.site_path, path), html)
+                .into_bytes(),
+            Err(_) => buf,
+        };
+
         Ok(Resource {
             original_path: path.to_owned(),
-            contents: buf,
+            contents: contents.into(),
+            dependencies: self.assets.sources(),
             ..Default::default()
         })
     }
+
+    /// Pages are rewritten once the assets have been fingerprinted.
+    fn stage(&self) -> u32 {
+        1
+    }
+}
+
+/// Serves the `[redirects]` from `lumin.toml`. There are no files to process, only the
+/// redirects to add when flushed.
+#[derive(Debug)]
+pub struct RedirectsProcessor {
+    config_path: PathBuf,
+    redirects: Vec<(String, Redirect)>,
+}
+
+impl RedirectsProcessor {
+    pub fn new(config: &Config) -> Result<RedirectsProcessor, Box<dyn Error>> {
+        let config_path = config.site_path.join(config::CONFIG_FILE);
+        let redirects = config
+            .redirects
+            .iter()
+            .map(|(url, rule)| {
+                let redirect = rule
+                    .redirect()
+                    .map_err(|e| ProcessError::new(&config_path, format!("{}: {}", url, e)))?;
+                Ok((redirects::url_path(url), redirect))
+            })
+            .collect::<Result<_, ProcessError>>()?;
+
+        Ok(RedirectsProcessor {
+            config_path,
+            redirects,
+        })
+    }
+}
+
+impl ResourceProcessor for RedirectsProcessor {
+    fn matches(&self, _path: &Path) -> bool {
+        false
+    }
+
+    fn process(&self, path: &Path) -> Result<Resource, Box<dyn Error>> {
+        Err(ProcessError::new(path, "redirects don't process files").into())
+    }
+
+    #[instrument]
+    fn flush(&self) -> Result<Vec<Resource>, Box<dyn Error>> {
+        Ok(self
+            .redirects
+            .iter()
+            .map(|(url, redirect)| Resource {
+                original_path: self.config_path.clone(),
+                url_path: URLPath::Absolute(url.clone()),
+                redirect: Some(redirect.clone()),
+                ..Default::default()
+            })
+            .collect())
+    }
+}
+
+/// Describes the finished site to crawlers with `sitemap.xml`, listing every page, and
+/// `robots.txt`, pointing at the sitemap and at what not to crawl.
+pub struct SitemapProcessor {
+    config_path: PathBuf,
+    /// What the URLs in the sitemap start with, without a trailing slash. The sitemap is left
+    /// out if this is empty, as it needs full URLs.
+    base_url: String,
+    exclude: GlobSet,
+    disallow: Vec<String>,
+    agents: BTreeMap<String, Vec<String>>,
+    posts: Arc<Mutex<HashMap<PathBuf, PostItem>>>,
+}
+
+impl SitemapProcessor {
+    pub fn new(
+        config: &Config,
+        posts: &PostsProcessor,
+    ) -> Result<SitemapProcessor, Box<dyn Error>> {
+        let config_path = config.site_path.join(config::CONFIG_FILE);
+
+        let mut exclude = GlobSetBuilder::new();
+        for glob in &config.sitemap.exclude {
+            let glob = Glob::new(glob.trim_start_matches('/'))
+                .map_err(|e| ProcessError::new(&config_path, format!("invalid glob: {}", e)))?;
+            exclude.add(glob);
+        }
+
+        let base_url = config
+            .sitemap
+            .base_url
+            .as_ref()
+            .unwrap_or(&config.site.base_url);
+
+        Ok(SitemapProcessor {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            exclude: exclude.build()?,
+            disallow: config.robots.disallow.clone(),
+            agents: config.robots.agents.clone(),
+            posts: posts.posts.clone(),
+            config_path,
+        })
+    }
+
+    /// Whether `url`, relative to the root of the site, belongs in the sitemap.
+    fn included(&self, url: &str, resource: &Resource) -> bool {
+        let path = format!("/{}", url);
+        url.ends_with(".html")
+            && url != "not_found.html"
+            && !resource.is_redirect()
+            && !self.exclude.is_match(url)
+            && !self.disallow.iter().any(|d| path.starts_with(d.as_str()))
+    }
+
+    fn sitemap(&self, resources: &HashMap<String, Arc<Resource>>) -> String {
+        let posts: HashMap<String, Option<DateTime<Utc>>> = {
+            let handle = self.posts.lock().unwrap();
+            handle
+                .values()
+                .map(|post| (post.link.clone(), post.lastmod))
+                .collect()
+        };
+
+        let mut urls: Vec<_> = resources
+            .iter()
+            .filter(|(url, resource)| self.included(url, resource))
+            .map(|(url, resource)| {
+                let lastmod = match posts.get(url.as_str()) {
+                    Some(lastmod) => *lastmod,
+                    None => resource.modified.map(DateTime::<Utc>::from),
+                };
+                (url.strip_suffix("index.html").unwrap_or(url), lastmod)
+            })
+            .collect();
+        urls.sort();
+
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+        for (url, lastmod) in urls {
+            let loc = format!("{}/{}", self.base_url, url);
+            xml.push_str("  <url>\n");
+            xml.push_str(&format!(
+                "    <loc>{}</loc>\n",
+                html_escape::encode_text(&loc)
+            ));
+            if let Some(lastmod) = lastmod {
+                xml.push_str(&format!(
+                    "    <lastmod>{}</lastmod>\n",
+                    lastmod.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                ));
+            }
+            xml.push_str("  </url>\n");
+        }
+        xml.push_str("</urlset>\n");
+        xml
+    }
+
+    fn robots(&self) -> String {
+        let mut txt = String::new();
+        let groups = std::iter::once(("*", &self.disallow)).chain(
+            self.agents
+                .iter()
+                .map(|(agent, paths)| (agent.as_str(), paths)),
+        );
+        for (agent, paths) in groups {
+            txt.push_str(&format!("User-agent: {}\n", agent));
+            if paths.is_empty() {
+                // An empty rule allows everything
+                txt.push_str("Disallow:\n");
+            }
+            for path in paths {
+                txt.push_str(&format!("Disallow: {}\n", path));
+            }
+            txt.push('\n');
+        }
+
+        if !self.base_url.is_empty() {
+            txt.push_str(&format!("Sitemap: {}/sitemap.xml\n", self.base_url));
+        }
+        txt
+    }
+}
+
+impl std::fmt::Debug for SitemapProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SitemapProcessor{}")
+    }
+}
+
+impl ResourceProcessor for SitemapProcessor {
+    fn matches(&self, _path: &Path) -> bool {
+        false
+    }
+
+    fn process(&self, path: &Path) -> Result<Resource, Box<dyn Error>> {
+        Err(ProcessError::new(path, "the sitemap doesn't process files").into())
+    }
+
+    #[instrument(skip(resources))]
+    fn summarise(
+        &self,
+        resources: &HashMap<String, Arc<Resource>>,
+    ) -> Result<Vec<Resource>, Box<dyn Error>> {
+        let mut summaries = vec![Resource {
+            original_path: self.config_path.clone(),
+            url_path: URLPath::Absolute("robots.txt".to_owned()),
+            contents: self.robots().into(),
+            ..Default::default()
+        }];
+
+        if self.base_url.is_empty() {
+            info!("no base_url, so leaving out the sitemap");
+        } else {
+            summaries.push(Resource {
+                original_path: self.config_path.clone(),
+                url_path: URLPath::Absolute("sitemap.xml".to_owned()),
+                contents: self.sitemap(resources).into(),
+                ..Default::default()
+            });
+        }
+
+        Ok(summaries)
+    }
 }
 
 pub struct LiquidProcessor {
-    partials_dir: PathBuf,
-    parser: liquid::Parser,
-    development: bool,
+    templates: Templates,
+    site_path: PathBuf,
+    posts_dir: PathBuf,
 }
 
 impl LiquidProcessor {
-    pub fn new(
-        partials_dir: PathBuf,
-        parser: liquid::Parser,
-        development: bool,
-    ) -> LiquidProcessor {
+    pub fn new(config: &Config, templates: Templates) -> LiquidProcessor {
         LiquidProcessor {
-            partials_dir,
-            parser,
-            development,
+            templates,
+            site_path: config.site_path.clone(),
+            posts_dir: config.posts_dir.clone(),
         }
     }
 }
@@ -67,118 +418,441 @@ impl std::fmt::Debug for LiquidProcessor {
 impl ResourceProcessor for LiquidProcessor {
     fn matches(&self, path: &Path) -> bool {
         path.extension().map(|e| e == "liquid").unwrap_or(false)
-            && !path.starts_with(&self.partials_dir)
+            && !path.starts_with(self.templates.partials_dir())
     }
 
     #[instrument]
     fn process(&self, path: &Path) -> Result<crate::store::Resource, Box<dyn Error>> {
         info!("liquid processing");
 
-        let tmpl = self.parser.parse_file(path)?;
-        let obj = liquid::object!({"development": self.development});
-
-        let mut buffer = Vec::new();
-        tmpl.render_to(&mut buffer, &obj)?;
+        let tmpl = self.templates.parser().parse_file(path)?;
+        let buffer = self.templates.render(&tmpl, &liquid::Object::new())?;
 
         let mut new_path = path.to_owned();
         new_path.set_extension("html");
 
+        let assets = self.templates.assets();
+        let contents = assets.rewrite(
+            &site_url(&self.site_path, &new_path),
+            std::str::from_utf8(&buffer)?,
+        );
+
+        // Any page can list the posts
+        let mut dependencies = vec![
+            self.templates.partials_dir().to_owned(),
+            self.posts_dir.clone(),
+        ];
+        dependencies.extend(assets.sources());
+
         Ok(Resource {
             original_path: path.to_owned(),
             url_path: URLPath::Filepath(new_path),
-            contents: buffer,
+            contents: contents.into(),
+            dependencies,
+            ..Default::default()
         })
     }
+
+    /// Pages are rendered once the posts have been collected.
+    fn stage(&self) -> u32 {
+        2
+    }
 }
 
 #[derive(Deserialize)]
 struct PostMetadata {
     title: String,
     description: String,
+    #[serde(deserialize_with = "deserialize_datetime")]
     published: toml::value::Datetime,
+    /// When the post was last changed in a way worth telling readers about
+    #[serde(default, deserialize_with = "deserialize_optional_datetime")]
+    updated: Option<toml::value::Datetime>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    draft: bool,
+    /// Old URLs of the post, which redirect to it
+    #[serde(default)]
+    aliases: Vec<Alias>,
+}
+
+/// Converts `datetime` to UTC, treating datetimes without an offset as already being in UTC.
+/// Returns `None` if there is no date.
+fn to_utc(datetime: &toml::value::Datetime) -> Option<DateTime<Utc>> {
+    let date = datetime.date?;
+    let date = NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())?;
+    let time = match datetime.time {
+        Some(t) => NaiveTime::from_hms_nano_opt(
+            t.hour.into(),
+            t.minute.into(),
+            t.second.into(),
+            t.nanosecond,
+        )?,
+        None => NaiveTime::MIN,
+    };
+
+    let utc = Utc.from_utc_datetime(&NaiveDateTime::new(date, time));
+    match datetime.offset {
+        Some(toml::value::Offset::Custom { minutes }) => {
+            Some(utc - chrono::Duration::minutes(minutes.into()))
+        }
+        _ => Some(utc),
+    }
+}
+
+/// Accepts a TOML datetime or, as YAML has no equivalent, a string in the same format.
+fn deserialize_datetime<'de, D>(deserializer: D) -> Result<toml::value::Datetime, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Datetime(toml::value::Datetime),
+        String(String),
+    }
+
+    match Value::deserialize(deserializer)? {
+        Value::Datetime(datetime) => Ok(datetime),
+        Value::String(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+fn deserialize_optional_datetime<'de, D>(
+    deserializer: D,
+) -> Result<Option<toml::value::Datetime>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_datetime(deserializer).map(Some)
+}
+
+enum FrontMatter<'a> {
+    Toml(&'a str),
+    Yaml(&'a str),
+}
+
+/// Splits a `+++` delimited TOML or `---` delimited YAML block off the top of `src`, returning it
+/// along with the rest of the document.
+fn split_front_matter(src: &str) -> (Option<FrontMatter<'_>>, &str) {
+    let mut lines = src.split_inclusive('\n');
+    let delimiter = match lines.next().map(str::trim_end) {
+        Some(d @ ("+++" | "---")) => d,
+        _ => return (None, src),
+    };
+
+    let start = src.find('\n').unwrap() + 1;
+    let mut end = start;
+    for line in lines {
+        if line.trim_end() == delimiter {
+            let matter = &src[start..end];
+            let rest = &src[end + line.len()..];
+            let matter = match delimiter {
+                "+++" => FrontMatter::Toml(matter),
+                _ => FrontMatter::Yaml(matter),
+            };
+            return (Some(matter), rest);
+        }
+
+        end += line.len();
+    }
+
+    (None, src)
+}
+
+#[derive(Serialize, Clone)]
+struct TagItem {
+    name: String,
+    slug: String,
+    link: String,
+}
+
+impl TagItem {
+    fn new(name: &str) -> Self {
+        let slug = slugify(name);
+        Self {
+            name: name.to_owned(),
+            link: format!("tags/{}/index.html", slug),
+            slug,
+        }
+    }
 }
 
 #[derive(Serialize)]
+struct TagSummary<'a> {
+    name: &'a str,
+    slug: &'a str,
+    link: &'a str,
+    count: usize,
+}
+
+/// Turns `s` into something usable in a URL, e.g. "Rust & C" becomes "rust-c".
+fn slugify(s: &str) -> String {
+    let mut slug = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_end_matches('-').to_owned()
+}
+
+/// When `path` was last modified, for things without a date of their own. Unlike the current
+/// time it stays the same between rebuilds, so what uses it does too.
+fn modified_at(path: &Path) -> DateTime<Utc> {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map(DateTime::from)
+        .unwrap_or_default()
+}
+
+#[derive(Serialize, Clone)]
 struct PostItem {
     filename: String,
     title: String,
     description: String,
     published: String,
+    updated: Option<String>,
     contents: String,
     link: String,
+    tags: Vec<TagItem>,
+    draft: bool,
+    scheduled: bool,
+    /// When the post was published, for feeds
+    #[serde(skip)]
+    published_at: Option<DateTime<Utc>>,
+    /// When the post last changed, for the sitemap and feeds
+    #[serde(skip)]
+    lastmod: Option<DateTime<Utc>>,
+    /// The file the post came from
+    #[serde(skip)]
+    source: PathBuf,
+}
+
+struct PostTemplates {
+    post: liquid::Template,
+    post_list: liquid::Template,
+    /// Rendered in place of the built in Atom feed
+    feed: Option<liquid::Template>,
+    tags: Option<liquid::Template>,
 }
 
 pub struct PostsProcessor {
+    site_path: PathBuf,
     posts_dir: PathBuf,
     posts_template_path: PathBuf,
-    post_template: liquid::Template,
     post_list_template_path: PathBuf,
-    post_list_template: liquid::Template,
-    feed_template: liquid::Template,
+    feed_template_path: PathBuf,
+    tags_template_path: PathBuf,
+
+    templates: Templates,
+    compiled: RwLock<PostTemplates>,
 
-    code_regex: Regex,
+    page_size: usize,
+    feed_length: usize,
+    feeds: FeedsConfig,
+    site: SiteConfig,
 
-    posts: Arc<Mutex<Vec<PostItem>>>,
-    highlighter: Arc<Mutex<highlight::Highlight>>,
+    posts: Arc<Mutex<HashMap<PathBuf, PostItem>>>,
+    /// Redirects from each post's aliases
+    aliases: Mutex<HashMap<PathBuf, Vec<(String, Redirect)>>>,
+    /// Posts that will be published in the future, and when
+    scheduled: Mutex<HashMap<PathBuf, DateTime<Utc>>>,
+    highlighter: highlight::Highlight,
 
     development: bool,
 }
 
 impl PostsProcessor {
     pub fn new(
-        posts_dir: PathBuf,
-        posts_template_path: PathBuf,
-        post_list_template_path: PathBuf,
-        feed_template_path: PathBuf,
-        parser: &liquid::Parser,
+        config: &Config,
+        templates: Templates,
         development: bool,
     ) -> Result<Self, Box<dyn Error>> {
-        let post_template = parser.parse_file(&posts_template_path)?;
-        let post_list_template = parser.parse_file(&post_list_template_path)?;
-        let feed_template = parser.parse_file(&feed_template_path)?;
+        let compiled = Self::compile(
+            &templates,
+            &config.post_template,
+            &config.post_list_template,
+            &config.feed_template,
+            &config.tags_template,
+        )?;
+
+        let mut highlighter = highlight::Highlight::new()?;
+        if config.grammars_dir.is_dir() {
+            highlighter
+                .load_grammars(&config.grammars_dir)
+                .map_err(|e| ProcessError::new(&config.grammars_dir, e))?;
+        }
+        highlighter
+            .add_aliases(&config.highlight.aliases)
+            .map_err(|e| ProcessError::new(config.site_path.join(config::CONFIG_FILE), e))?;
+
+        let feeds = &config.feeds;
+        if config.site.base_url.is_empty() && (feeds.atom || feeds.rss || feeds.json) {
+            warn!("no site.base_url, so leaving out the built in feeds");
+        }
+
+        if !config.tags_template.exists() {
+            info!(path = ?config.tags_template, "no tags template, so leaving out tags/index.html");
+        }
+
+        // Every Atom entry needs an author, and posts don't have their own
+        let builtin_atom = feeds.atom && !config.feed_template.exists();
+        if builtin_atom && !config.site.base_url.is_empty() && config.site.author.is_empty() {
+            return Err(ProcessError::new(
+                config.site_path.join(config::CONFIG_FILE),
+                "the Atom feed needs a site.author, or set feeds.atom = false",
+            )
+            .into());
+        }
+
         Ok(Self {
-            posts_dir,
-            post_template,
-            posts_template_path,
-            post_list_template_path,
-            post_list_template,
-            feed_template,
+            site_path: config.site_path.clone(),
+            posts_dir: config.posts_dir.clone(),
+            posts_template_path: config.post_template.clone(),
+            post_list_template_path: config.post_list_template.clone(),
+            feed_template_path: config.feed_template.clone(),
+            tags_template_path: config.tags_template.clone(),
+            templates,
+            compiled: RwLock::new(compiled),
+            page_size: config.page_size,
+            feed_length: config.feed_length,
+            feeds: config.feeds.clone(),
+            site: config.site.clone(),
             development,
             posts: Arc::default(),
-            highlighter: Arc::new(Mutex::new(highlight::Highlight::new()?)),
-            code_regex: RegexBuilder::new(
-                r#"<pre>\s*<code( class="language-(.*?)")?>(.+?)</code>\s*</pre>"#,
-            )
-            .multi_line(true)
-            .dot_matches_new_line(true)
-            .build()?,
+            aliases: Mutex::default(),
+            scheduled: Mutex::default(),
+            highlighter,
         })
     }
 
-    #[instrument]
-    fn get_metadata(&self, mut path: PathBuf) -> Result<PostMetadata, Box<dyn Error>> {
-        path.set_extension("toml");
+    fn compile(
+        templates: &Templates,
+        posts_template_path: &Path,
+        post_list_template_path: &Path,
+        feed_template_path: &Path,
+        tags_template_path: &Path,
+    ) -> Result<PostTemplates, Box<dyn Error>> {
+        let parser = templates.parser();
+        let parse = |path: &Path| {
+            parser
+                .parse_file(path)
+                .map_err(|e| ProcessError::new(path, e))
+        };
 
-        let buf = std::fs::read_to_string(path)?;
-        Ok(toml::from_str(&buf)?)
+        Ok(PostTemplates {
+            post: parse(posts_template_path)?,
+            post_list: parse(post_list_template_path)?,
+            feed: match feed_template_path.exists() {
+                true => Some(parse(feed_template_path)?),
+                false => None,
+            },
+            // Only sites that want an overview of their tags need this template
+            tags: match tags_template_path.exists() {
+                true => Some(parse(tags_template_path)?),
+                false => None,
+            },
+        })
+    }
+
+    /// Returns the scheduled posts which are due to be published by `now`, along with when the
+    /// next one after that is.
+    pub fn due(&self, now: DateTime<Utc>) -> (Vec<PathBuf>, Option<DateTime<Utc>>) {
+        let handle = self.scheduled.lock().unwrap();
+        let due = handle
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        let next = handle.values().filter(|at| **at > now).min().copied();
+        (due, next)
+    }
+
+    pub fn is_template(&self, path: &Path) -> bool {
+        path == self.posts_template_path
+            || path == self.post_list_template_path
+            || path == self.feed_template_path
+            || path == self.tags_template_path
+    }
+
+    fn metadata_path(path: &Path) -> PathBuf {
+        path.with_extension("toml")
+    }
+
+    /// Reads the post's metadata from its front matter or, failing that, from a TOML file of the
+    /// same name.
+    #[instrument(skip(front_matter))]
+    fn get_metadata(
+        &self,
+        path: &Path,
+        front_matter: Option<FrontMatter<'_>>,
+    ) -> Result<PostMetadata, Box<dyn Error>> {
+        match front_matter {
+            Some(FrontMatter::Toml(s)) => {
+                return Ok(toml::from_str(s).map_err(|e| ProcessError::new(path, e))?)
+            }
+            Some(FrontMatter::Yaml(s)) => {
+                return Ok(serde_yaml::from_str(s).map_err(|e| ProcessError::new(path, e))?)
+            }
+            None => {}
+        }
+
+        let metadata_path = Self::metadata_path(path);
+        if !metadata_path.exists() {
+            return Err(ProcessError::new(
+                path,
+                format!(
+                    "no metadata: there is no front matter in the post and {:?} does not exist",
+                    metadata_path
+                ),
+            )
+            .into());
+        }
+
+        let buf = std::fs::read_to_string(&metadata_path)
+            .map_err(|e| ProcessError::new(&metadata_path, e))?;
+        Ok(toml::from_str(&buf).map_err(|e| ProcessError::new(&metadata_path, e))?)
+    }
+
+    /// Renders `posts` into pages under `dir`.
+    fn render_post_lists(
+        &self,
+        dir: &str,
+        posts: &[&PostItem],
+        tag: Option<&TagItem>,
+    ) -> Result<Vec<Resource>, Box<dyn Error>> {
+        let chunks: Vec<_> = posts.chunks(self.page_size).collect();
+        let len = chunks.len();
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| self.render_post_list(dir, i, i == len - 1, chunk, tag))
+            .collect()
     }
 
     fn render_post_list(
         &self,
+        dir: &str,
         i: usize,
         last: bool,
-        posts: &[PostItem],
+        posts: &[&PostItem],
+        tag: Option<&TagItem>,
     ) -> Result<Resource, Box<dyn Error>> {
         let new_path = if i == 0 {
-            "posts/index.html".to_owned()
+            format!("{}/index.html", dir)
         } else {
-            format!("posts/posts-{}.html", i)
+            format!("{}/posts-{}.html", dir, i)
         };
 
         let previous = match i {
             0 => "".to_owned(),
-            1 => "/posts/index.html".to_owned(),
-            _ => format!("/posts/posts-{}.html", i - 1),
+            1 => format!("/{}/index.html", dir),
+            _ => format!("/{}/posts-{}.html", dir, i - 1),
         };
         let next = if last {
             "".to_owned()
@@ -186,66 +860,290 @@ impl PostsProcessor {
             format!("posts-{}.html", i + 1)
         };
 
-        let obj = liquid::object!({"posts": posts, "previous": previous, "next": next ,"development": self.development});
-        let mut buf = Vec::new();
-        self.post_list_template.render_to(&mut buf, &obj)?;
+        let obj = liquid::object!({"posts": posts, "previous": previous, "next": next, "tag": tag});
+        let buf = self
+            .templates
+            .render(&self.compiled.read().unwrap().post_list, &obj)?;
+        let contents = self.rewrite_assets(&new_path, &buf)?;
 
         Ok(Resource {
             original_path: self.post_list_template_path.clone(),
             url_path: URLPath::Absolute(new_path),
-            contents: buf,
+            contents: contents.into(),
+            dependencies: self.page_dependencies(),
+            ..Default::default()
         })
     }
 
-    fn render_feed(&self, posts: &[PostItem]) -> Result<Resource, Box<dyn Error>> {
-        info!("generating feed");
-        let obj = liquid::object!({ "items": &posts[..std::cmp::min(10, posts.len())] });
-        let contents = self.feed_template.render(&obj)?;
-        Ok(Resource {
-            original_path: "feed.liquid".into(),
-            url_path: URLPath::Absolute("atom.xml".to_string()),
-            contents: contents.as_bytes().to_owned(),
-        })
+    /// Renders the feeds of `posts` into `dir`, which is either empty or ends in a slash. Atom
+    /// comes from `feed_template` if the site has one.
+    fn render_feeds(
+        &self,
+        dir: &str,
+        posts: &[&PostItem],
+        tag: Option<&TagItem>,
+    ) -> Result<Vec<Resource>, Box<dyn Error>> {
+        let posts = &posts[..std::cmp::min(self.feed_length, posts.len())];
+        let resource = |url: String, contents: Vec<u8>| Resource {
+            original_path: self.feed_template_path.clone(),
+            url_path: URLPath::Absolute(url),
+            contents: contents.into(),
+            dependencies: vec![
+                self.posts_dir.clone(),
+                self.templates.partials_dir().to_owned(),
+            ],
+            ..Default::default()
+        };
+
+        let mut resources = Vec::new();
+        let compiled = self.compiled.read().unwrap();
+        if let (true, Some(template)) = (self.feeds.atom, &compiled.feed) {
+            let url = format!("{}atom.xml", dir);
+            info!(url, "generating feed from template");
+            let obj = liquid::object!({ "items": posts, "tag": tag });
+            resources.push(resource(url, self.templates.render(template, &obj)?));
+        }
+
+        // The built in feeds need absolute URLs
+        if self.site.base_url.is_empty() {
+            return Ok(resources);
+        }
+
+        let feed = self.feed(dir, posts, tag);
+        if self.feeds.atom && compiled.feed.is_none() {
+            let url = format!("{}atom.xml", dir);
+            info!(url, "generating feed");
+            resources.push(resource(url.clone(), feed.atom(&url).into_bytes()));
+        }
+        if self.feeds.rss {
+            let url = format!("{}rss.xml", dir);
+            info!(url, "generating feed");
+            resources.push(resource(url.clone(), feed.rss(&url).into_bytes()));
+        }
+        if self.feeds.json {
+            let url = format!("{}feed.json", dir);
+            info!(url, "generating feed");
+            resources.push(resource(url.clone(), feed.json(&url)?.into_bytes()));
+        }
+
+        Ok(resources)
     }
 
-    #[instrument(skip(src))]
-    fn highlight_code(&self, src: &str) -> Result<String, Box<dyn Error>> {
-        let mut contents = src.to_owned();
-        for c in self.code_regex.captures_iter(src) {
-            let all = c.get(0).unwrap();
-            let code = c.get(3).unwrap().as_str();
+    fn feed<'a>(&'a self, dir: &str, posts: &[&'a PostItem], tag: Option<&TagItem>) -> Feed<'a> {
+        let base_url = self.site.base_url.trim_end_matches('/');
+        let description = self
+            .site
+            .extra
+            .get("description")
+            .and_then(|d| d.as_str())
+            .unwrap_or_default();
 
-            let language = c.get(2).map(|c| c.as_str()).unwrap_or("");
-            debug!(
-                start = all.start(),
-                end = all.end(),
-                language,
-                "got code match"
-            );
+        let items = posts
+            .iter()
+            .map(|post| {
+                let published = post
+                    .published_at
+                    .unwrap_or_else(|| modified_at(&post.source));
+                let content = match self.feeds.content {
+                    FeedContent::Full => {
+                        let html = self.templates.assets().rewrite(&post.link, &post.contents);
+                        Some(feeds::absolute_urls(&html, base_url, &post.link))
+                    }
+                    FeedContent::Summary => None,
+                };
+
+                feeds::Item {
+                    title: &post.title,
+                    link: &post.link,
+                    summary: &post.description,
+                    content,
+                    published,
+                    updated: post.lastmod.unwrap_or(published),
+                    tags: post.tags.iter().map(|tag| tag.name.as_str()).collect(),
+                }
+            })
+            .collect();
+
+        Feed {
+            title: match tag {
+                Some(tag) => format!("{}: {}", self.site.title, tag.name),
+                None => self.site.title.clone(),
+            },
+            description,
+            author: &self.site.author,
+            base_url,
+            home: dir.to_owned(),
+            modified: modified_at(&self.posts_dir),
+            items,
+        }
+    }
+
+    /// Renders a list of posts and a feed for every tag, along with an overview of all the tags
+    /// if the site has a template for it.
+    fn render_tags(
+        &self,
+        tags: &BTreeMap<&str, (&TagItem, Vec<&PostItem>)>,
+        summaries: &[TagSummary],
+    ) -> Result<Vec<Resource>, Box<dyn Error>> {
+        let mut resources = Vec::new();
+        for (slug, (tag, posts)) in tags {
+            let dir = format!("tags/{}", slug);
+            resources.extend(self.render_post_lists(&dir, posts, Some(tag))?);
+            resources.extend(self.render_feeds(&format!("{}/", dir), posts, Some(tag))?);
+        }
+
+        let compiled = self.compiled.read().unwrap();
+        let Some(template) = &compiled.tags else {
+            return Ok(resources);
+        };
+
+        let obj = liquid::object!({ "tags": summaries });
+        let buf = self.templates.render(template, &obj)?;
+        let url = "tags/index.html".to_owned();
+
+        resources.push(Resource {
+            original_path: self.tags_template_path.clone(),
+            contents: self.rewrite_assets(&url, &buf)?.into(),
+            url_path: URLPath::Absolute(url),
+            dependencies: self.page_dependencies(),
+            ..Default::default()
+        });
+
+        Ok(resources)
+    }
+
+    /// Points references to fingerprinted assets in the page at `url` to their new URLs.
+    fn rewrite_assets(&self, url: &str, html: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let html = std::str::from_utf8(html)?;
+        Ok(self.templates.assets().rewrite(url, html).into_bytes())
+    }
 
-            let mut highlighter = self.highlighter.lock().map_err(|e| e.to_string())?;
-            if !highlighter.supported(language) {
-                contents = contents.replace(
-                    all.as_str(),
-                    &format!(r#"<pre class="code-listing"><code>{}</code></pre>"#, code),
-                );
+    /// The files every page listing posts is built from.
+    fn page_dependencies(&self) -> Vec<PathBuf> {
+        let mut dependencies = vec![
+            self.posts_dir.clone(),
+            self.templates.partials_dir().to_owned(),
+        ];
+        dependencies.extend(self.templates.assets().sources());
+        dependencies
+    }
+
+    /// Renders a post's Markdown to HTML, highlighting its code blocks.
+    ///
+    /// The code blocks are found in the Markdown's syntax tree, for their language, then swapped
+    /// for their highlighted versions in the HTML. Before rendering, each fenced block's info
+    /// string is swapped for a marker naming the block, using its position in the tree, so each
+    /// block in the HTML is matched up with its own, wherever it ends up. Raw HTML in posts is
+    /// escaped, so every `<pre><code>` in the output is one of them.
+    #[instrument(skip(body))]
+    fn render_markdown(&self, body: &str) -> Result<String, Box<dyn Error>> {
+        const MARKER: &str = "lumin-code-";
+
+        let options = markdown::Options::gfm();
+        let tree = markdown::to_mdast(body, &options.parse)?;
+
+        let mut blocks = Vec::new();
+        code_blocks(&tree, &mut blocks);
+        blocks.sort_by_key(|block| block.position.as_ref().map(|p| p.start.offset));
+
+        let mut source = String::with_capacity(body.len());
+        let mut fenced = Vec::new();
+        let mut copied = 0;
+        for block in blocks {
+            let Some(position) = &block.position else {
+                continue;
+            };
+            let start = position.start.offset;
+            let line = body[start..].split('\n').next().unwrap_or_default();
+            // Indented blocks have no info string, so can't have a language either
+            let fence = line.len() - line.trim_start_matches(['`', '~']).len();
+            if fence < 3 {
                 continue;
             }
 
-            let code = {
-                let code = &html_escape::decode_html_entities(code);
-                highlighter.highlight(&c[2], code.as_bytes())?
+            source.push_str(&body[copied..start + fence]);
+            source.push_str(&format!("{}{}", MARKER, fenced.len()));
+            copied = start + line.len();
+            fenced.push(block);
+        }
+        source.push_str(&body[copied..]);
+
+        let html = markdown::to_html_with_options(&source, &options)?;
+        if fenced.is_empty() {
+            return Ok(html);
+        }
+
+        const OPEN: &str = "<pre><code";
+        const CLOSE: &str = "</code></pre>";
+
+        let mut out = String::with_capacity(html.len() * 2);
+        let mut rest = html.as_str();
+        while let Some(start) = rest.find(OPEN) {
+            let Some(len) = rest[start..].find(CLOSE) else {
+                break;
+            };
+            let block = &rest[start + OPEN.len()..start + len];
+            let Some((attributes, code)) = block.split_once('>') else {
+                break;
             };
-            contents = contents.replace(
-                all.as_str(),
-                &format!(
-                    r#"<pre class="code-listing"><code>{}</code></pre>"#,
-                    std::str::from_utf8(&code)?
+            out.push_str(&rest[..start]);
+            rest = &rest[start + len + CLOSE.len()..];
+
+            let found = attributes
+                .strip_prefix(" class=\"language-")
+                .and_then(|class| class.strip_prefix(MARKER))
+                .and_then(|class| class.strip_suffix('"'))
+                .and_then(|i| i.parse::<usize>().ok())
+                .and_then(|i| fenced.get(i));
+
+            // The Markdown parser splits the info string at the first space, so join it back up
+            let (info, attributes) = match found {
+                Some(block) => (
+                    format!(
+                        "{} {}",
+                        block.lang.as_deref().unwrap_or_default(),
+                        block.meta.as_deref().unwrap_or_default()
+                    ),
+                    match &block.lang {
+                        Some(lang) => format!(
+                            " class=\"language-{}\"",
+                            html_escape::encode_double_quoted_attribute(lang)
+                        ),
+                        None => String::new(),
+                    },
                 ),
-            );
+                None => (String::new(), attributes.to_owned()),
+            };
+            let language = self.highlighter.language_for_info(&info);
+            debug!(info, language, "highlighting code block");
+
+            let code = html_escape::decode_html_entities(code);
+            out.push_str(r#"<pre class="code-listing"><code"#);
+            out.push_str(&attributes);
+            out.push('>');
+            match language {
+                Some(language) => {
+                    let highlighted = self.highlighter.highlight(language, code.as_bytes())?;
+                    out.push_str(std::str::from_utf8(&highlighted)?);
+                }
+                None => out.push_str(&html_escape::encode_text(&code)),
+            }
+            out.push_str(CLOSE);
         }
+        out.push_str(rest);
+
+        Ok(out)
+    }
+}
 
-        Ok(contents)
+/// Collects the code blocks in `node` and its descendants, in the order they're written.
+fn code_blocks<'a>(node: &'a markdown::mdast::Node, blocks: &mut Vec<&'a markdown::mdast::Code>) {
+    if let markdown::mdast::Node::Code(code) = node {
+        blocks.push(code);
+    }
+
+    for child in node.children().into_iter().flatten() {
+        code_blocks(child, blocks);
     }
 }
 
@@ -257,7 +1155,7 @@ impl std::fmt::Debug for PostsProcessor {
 
 impl ResourceProcessor for PostsProcessor {
     fn matches(&self, path: &Path) -> bool {
-        if path == self.posts_template_path || path == self.post_list_template_path {
+        if self.is_template(path) {
             return true;
         }
 
@@ -270,9 +1168,9 @@ impl ResourceProcessor for PostsProcessor {
 
     #[instrument]
     fn process(&self, path: &Path) -> Result<Resource, Box<dyn Error>> {
-        if path == self.posts_template_path || path == self.post_list_template_path {
+        if self.is_template(path) {
             return Ok(Resource {
-                contents: vec![],
+                contents: Bytes::new(),
                 original_path: path.to_owned(),
                 ..Default::default()
             });
@@ -281,60 +1179,219 @@ impl ResourceProcessor for PostsProcessor {
         info!("post processing");
 
         let buf = std::fs::read_to_string(path)?;
-        let html = markdown::to_html_with_options(&buf, &markdown::Options::gfm())?;
+        let (front_matter, body) = split_front_matter(&buf);
+        let html = self.render_markdown(body)?;
+
+        let meta = self.get_metadata(path, front_matter)?;
+        let tags: Vec<_> = meta.tags.iter().map(|t| TagItem::new(t)).collect();
+        if let Some(tag) = tags.iter().find(|tag| tag.slug.is_empty()) {
+            return Err(ProcessError::new(
+                path,
+                format!("tag {:?} has nothing in it that can go in a URL", tag.name),
+            )
+            .into());
+        }
+
+        let mut new_path = path.to_owned();
+        new_path.set_extension("html");
 
-        let meta = self.get_metadata(path.to_owned())?;
+        let mut dependencies = vec![
+            Self::metadata_path(path),
+            self.posts_template_path.clone(),
+            self.templates.partials_dir().to_owned(),
+        ];
+        dependencies.extend(self.templates.assets().sources());
+
+        let publish_at = to_utc(&meta.published).filter(|at| *at > Utc::now());
+        let scheduled = publish_at.is_some();
+        {
+            let mut handle = self.scheduled.lock().unwrap();
+            match publish_at {
+                Some(at) => handle.insert(path.to_owned(), at),
+                None => handle.remove(path),
+            };
+        }
+
+        // Drafts and scheduled posts are only shown while developing the site
+        if (meta.draft || scheduled) && !self.development {
+            info!(draft = meta.draft, scheduled, "skipping unpublished post");
+            self.posts.lock().unwrap().remove(path);
+            self.aliases.lock().unwrap().remove(path);
+            return Ok(Resource {
+                original_path: path.to_owned(),
+                url_path: URLPath::Filepath(new_path),
+                contents: Bytes::new(),
+                dependencies,
+                ..Default::default()
+            });
+        }
+
+        let link = site_url(&self.site_path, &new_path);
+        let aliases = meta
+            .aliases
+            .iter()
+            .map(|alias| alias.redirect(&format!("/{}", link)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ProcessError::new(path, e))?;
+        self.aliases
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), aliases);
 
         let obj = liquid::object!({
             "contents": html,
             "post_title": meta.title,
             "post_published": meta.published.to_string(),
+            "post_updated": meta.updated.map(|updated| updated.to_string()),
             "post_description": meta.description,
-            "development": self.development
+            "post_tags": tags,
+            "draft": meta.draft,
+            "scheduled": scheduled,
         });
-        let contents = self.highlight_code(&self.post_template.render(&obj)?)?;
-
-        let mut new_path = path.to_owned();
-        new_path.set_extension("html");
+        let rendered = self
+            .templates
+            .render_post(&self.compiled.read().unwrap().post, &obj)?;
+        let contents = self.templates.assets().rewrite(
+            &site_url(&self.site_path, &new_path),
+            std::str::from_utf8(&rendered)?,
+        );
 
         {
             let mut handle = self.posts.lock().map_err(|e| e.to_string())?;
-            handle.push(PostItem {
-                filename: new_path.file_name().unwrap().to_string_lossy().into(),
-                title: meta.title,
-                description: meta.description,
-                published: meta.published.to_string(),
-                contents: html,
-                link: "posts/".to_string()
-                    + &new_path.file_name().unwrap().to_string_lossy().to_string(),
-            })
+            handle.insert(
+                path.to_owned(),
+                PostItem {
+                    filename: new_path.file_name().unwrap().to_string_lossy().into(),
+                    title: meta.title,
+                    description: meta.description,
+                    published: meta.published.to_string(),
+                    updated: meta.updated.map(|updated| updated.to_string()),
+                    contents: html,
+                    link,
+                    tags,
+                    draft: meta.draft,
+                    scheduled,
+                    published_at: to_utc(&meta.published),
+                    lastmod: to_utc(meta.updated.as_ref().unwrap_or(&meta.published)),
+                    source: path.to_owned(),
+                },
+            );
         }
 
         Ok(Resource {
             original_path: path.to_owned(),
             url_path: URLPath::Filepath(new_path),
-            contents: contents.as_bytes().to_owned(),
+            contents: contents.into(),
+            dependencies,
+            ..Default::default()
         })
     }
 
     #[instrument]
     fn flush(&self) -> Result<Vec<Resource>, Box<dyn Error>> {
-        let mut handle = self.posts.lock().map_err(|e| e.to_string())?;
-        let mut posts = std::mem::take(&mut *handle);
+        let mut posts: Vec<_> = {
+            let handle = self.posts.lock().map_err(|e| e.to_string())?;
+            handle.values().cloned().collect()
+        };
 
         posts.sort_by(|a, b| a.published.cmp(&b.published).reverse());
+        let posts: Vec<_> = posts.iter().collect();
 
-        let chunks: Vec<_> = posts.chunks(10).collect();
-        let len = chunks.len();
-        let resources: Result<Vec<_>, Box<dyn Error>> = chunks
-            .into_iter()
-            .enumerate()
-            .map(|(i, chunk)| self.render_post_list(i, i == len - 1, chunk))
+        let mut tags: BTreeMap<&str, (&TagItem, Vec<&PostItem>)> = BTreeMap::new();
+        for post in &posts {
+            for tag in &post.tags {
+                let (first, tagged) = tags.entry(&tag.slug).or_insert((tag, vec![]));
+                // Tags that only differ in case are the same tag, shown as first written
+                if first.name.to_lowercase() != tag.name.to_lowercase() {
+                    return Err(ProcessError::new(
+                        &post.source,
+                        format!(
+                            "tags {:?} and {:?} would both be at {}",
+                            first.name, tag.name, tag.link
+                        ),
+                    )
+                    .into());
+                }
+                tagged.push(post);
+            }
+        }
+
+        let summaries: Vec<_> = tags
+            .values()
+            .map(|(tag, posts)| TagSummary {
+                name: &tag.name,
+                slug: &tag.slug,
+                link: &tag.link,
+                count: posts.len(),
+            })
             .collect();
 
-        let mut resources = resources?;
-        resources.push(self.render_feed(&posts)?);
+        self.templates.set_posts(
+            liquid::model::to_value(&posts)?,
+            liquid::model::to_value(&summaries)?,
+        );
+
+        let posts_url = site_url(&self.site_path, &self.posts_dir);
+        let mut resources = self.render_post_lists(&posts_url, &posts, None)?;
+        resources.extend(self.render_feeds("", &posts, None)?);
+        resources.extend(self.render_tags(&tags, &summaries)?);
+
+        // An alias can't be at the URL of a page, as its redirect would replace it
+        let mut taken: HashSet<String> = posts.iter().map(|post| post.link.clone()).collect();
+        taken.extend(resources.iter().filter_map(|res| match &res.url_path {
+            URLPath::Absolute(url) => Some(url.clone()),
+            _ => None,
+        }));
+
+        let aliases = self.aliases.lock().unwrap();
+        for (path, aliases) in aliases.iter() {
+            for (url, redirect) in aliases {
+                if !taken.insert(url.clone()) {
+                    return Err(ProcessError::new(
+                        path,
+                        format!("alias /{} is already taken by another page or alias", url),
+                    )
+                    .into());
+                }
+                resources.push(Resource {
+                    original_path: path.clone(),
+                    url_path: URLPath::Absolute(url.clone()),
+                    redirect: Some(redirect.clone()),
+                    ..Default::default()
+                });
+            }
+        }
 
         Ok(resources)
     }
+
+    /// Posts can refer to fingerprinted assets, so are rendered after them.
+    fn stage(&self) -> u32 {
+        1
+    }
+
+    fn remove(&self, path: &Path) {
+        let mut handle = self.posts.lock().unwrap();
+        handle.retain(|post, _| !post.starts_with(path));
+
+        let mut handle = self.scheduled.lock().unwrap();
+        handle.retain(|post, _| !post.starts_with(path));
+
+        let mut handle = self.aliases.lock().unwrap();
+        handle.retain(|post, _| !post.starts_with(path));
+    }
+
+    fn reload(&self) -> Result<(), Box<dyn Error>> {
+        info!("reloading post templates");
+
+        let compiled = Self::compile(
+            &self.templates,
+            &self.posts_template_path,
+            &self.post_list_template_path,
+            &self.feed_template_path,
+            &self.tags_template_path,
+        )?;
+        *self.compiled.write().unwrap() = compiled;
+        Ok(())
+    }
 }
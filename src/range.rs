@@ -0,0 +1,181 @@
+use std::ops::RangeInclusive;
+
+/// Parses a `Range` header for a resource `len` bytes long into the byte ranges it asks for.
+///
+/// Returns `None` if the header isn't understood, in which case it should be ignored and the
+/// whole resource sent, as it is if it asks for more than `MAX_RANGES` ranges. Returns an empty
+/// list if none of the ranges can be satisfied.
+pub fn parse(header: &str, len: u64) -> Option<Vec<RangeInclusive<u64>>> {
+    let (unit, specs) = header.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let (start, end) = spec.trim().split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        let range = if start.is_empty() {
+            // The last `end` bytes
+            let suffix: u64 = end.parse().ok()?;
+            if suffix == 0 || len == 0 {
+                continue;
+            }
+            len.saturating_sub(suffix)..=len - 1
+        } else {
+            let start: u64 = start.parse().ok()?;
+            let end = match end {
+                "" => u64::MAX,
+                end => end.parse().ok()?,
+            };
+            if end < start {
+                return None;
+            }
+            if start >= len {
+                continue;
+            }
+            start..=end.min(len - 1)
+        };
+
+        ranges.push(range);
+    }
+
+    if ranges.len() > MAX_RANGES {
+        return None;
+    }
+
+    Some(ranges)
+}
+
+/// The most ranges a request can ask for before the header is ignored, so overlapping or
+/// tiny ranges can't turn one request into lots of work.
+const MAX_RANGES: usize = 16;
+
+/// Formats the `Content-Range` of `range` in a resource `len` bytes long.
+pub fn content_range(range: &RangeInclusive<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start(), range.end(), len)
+}
+
+/// A `multipart/byteranges` body, which is how several ranges are sent in one response.
+pub struct Multipart {
+    pub boundary: String,
+    content_type: String,
+    len: u64,
+}
+
+impl Multipart {
+    pub fn new(content_type: String, len: u64) -> Self {
+        Self {
+            boundary: format!("{:016x}", rand::random::<u64>()),
+            content_type,
+            len,
+        }
+    }
+
+    /// The `Content-Type` of the whole response.
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    /// What goes before the bytes of `range`. `first` is whether it is the first part.
+    pub fn part_header(&self, range: &RangeInclusive<u64>, first: bool) -> String {
+        format!(
+            "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            if first { "" } else { "\r\n" },
+            self.boundary,
+            self.content_type,
+            content_range(range, self.len)
+        )
+    }
+
+    /// What goes after the last part.
+    pub fn end(&self) -> String {
+        format!("\r\n--{}--\r\n", self.boundary)
+    }
+
+    /// The length of the whole body.
+    pub fn body_len(&self, ranges: &[RangeInclusive<u64>]) -> u64 {
+        let parts: u64 = ranges
+            .iter()
+            .enumerate()
+            .map(|(i, range)| {
+                self.part_header(range, i == 0).len() as u64 + range.end() - range.start() + 1
+            })
+            .sum();
+        parts + self.end().len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse("bytes=0-499", 1000), Some(vec![0..=499]));
+        assert_eq!(parse("Bytes = 0-0, -1", 1000), Some(vec![0..=0, 999..=999]));
+    }
+
+    #[test]
+    fn open_ranges_run_to_the_end() {
+        assert_eq!(parse("bytes=500-", 1000), Some(vec![500..=999]));
+        assert_eq!(parse("bytes=900-2000", 1000), Some(vec![900..=999]));
+    }
+
+    #[test]
+    fn suffix_ranges_are_the_last_bytes() {
+        assert_eq!(parse("bytes=-100", 1000), Some(vec![900..=999]));
+        assert_eq!(parse("bytes=-5000", 1000), Some(vec![0..=999]));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges_are_left_out() {
+        assert_eq!(parse("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(parse("bytes=-0", 1000), Some(vec![]));
+        assert_eq!(parse("bytes=0-, -1", 0), Some(vec![]));
+        assert_eq!(parse("bytes=2000-3000, 0-1", 1000), Some(vec![0..=1]));
+    }
+
+    #[test]
+    fn invalid_headers_are_ignored() {
+        assert_eq!(parse("bytes=500-499", 1000), None);
+        assert_eq!(parse("items=0-1", 1000), None);
+        assert_eq!(parse("bytes=a-b", 1000), None);
+        assert_eq!(parse("bytes=5", 1000), None);
+        assert_eq!(parse("bytes 0-1", 1000), None);
+    }
+
+    #[test]
+    fn too_many_ranges_are_ignored() {
+        let specs = |n: usize| vec!["0-0"; n].join(",");
+        assert_eq!(
+            parse(&format!("bytes={}", specs(MAX_RANGES)), 10).map(|r| r.len()),
+            Some(MAX_RANGES)
+        );
+        assert_eq!(parse(&format!("bytes={}", specs(MAX_RANGES + 1)), 10), None);
+    }
+
+    #[test]
+    fn formats_content_range() {
+        assert_eq!(content_range(&(0..=499), 1000), "bytes 0-499/1000");
+    }
+
+    #[test]
+    fn multipart_body_len_matches_body() {
+        let contents: Vec<u8> = (0..=255).collect();
+        let multipart = Multipart::new("text/plain".to_owned(), contents.len() as u64);
+        let ranges = [0..=9, 100..=100, 250..=255];
+
+        let mut body = Vec::new();
+        for (i, range) in ranges.iter().enumerate() {
+            body.extend_from_slice(multipart.part_header(range, i == 0).as_bytes());
+            body.extend_from_slice(&contents[*range.start() as usize..=*range.end() as usize]);
+        }
+        body.extend_from_slice(multipart.end().as_bytes());
+
+        assert_eq!(multipart.body_len(&ranges), body.len() as u64);
+        assert!(multipart.content_type().ends_with(&multipart.boundary));
+        assert!(body.starts_with(format!("--{}\r\n", multipart.boundary).as_bytes()));
+    }
+}
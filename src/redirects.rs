@@ -0,0 +1,124 @@
+use axum::http::StatusCode;
+use serde::Deserialize;
+
+/// What a URL that has moved, or gone for good, is answered with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Redirect {
+    /// 301 Moved Permanently
+    Permanent(String),
+    /// 302 Found
+    Temporary(String),
+    /// 410 Gone
+    Gone,
+}
+
+impl Redirect {
+    pub fn new(status: u16, to: Option<String>) -> Result<Self, String> {
+        match (status, to) {
+            (301, Some(to)) => Ok(Redirect::Permanent(to)),
+            (302, Some(to)) => Ok(Redirect::Temporary(to)),
+            (301 | 302, None) => Err(format!("a {} redirect needs somewhere to go", status)),
+            (410, _) => Ok(Redirect::Gone),
+            (status, _) => Err(format!("redirects can be 301, 302 or 410, not {}", status)),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Redirect::Permanent(_) => StatusCode::MOVED_PERMANENTLY,
+            Redirect::Temporary(_) => StatusCode::FOUND,
+            Redirect::Gone => StatusCode::GONE,
+        }
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        match self {
+            Redirect::Permanent(to) | Redirect::Temporary(to) => Some(to),
+            Redirect::Gone => None,
+        }
+    }
+
+    /// A page that sends browsers on to the new location, for static hosts that can't
+    /// redirect themselves.
+    pub fn stub(&self) -> Option<String> {
+        let to = html_escape::encode_double_quoted_attribute(self.location()?);
+        Some(format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Redirecting</title>\n\
+             <meta http-equiv=\"refresh\" content=\"0; url={to}\">\n<link rel=\"canonical\" href=\"{to}\">\n\
+             </head>\n<body>\n<a href=\"{to}\">This page has moved.</a>\n</body>\n</html>\n",
+            to = to
+        ))
+    }
+}
+
+/// A redirect as written in the `[redirects]` table of `lumin.toml`: either just where to go,
+/// for a 301, or a table like `{ to = "/new.html", status = 302 }`. A 410 doesn't need a `to`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RedirectRule {
+    To(String),
+    With {
+        to: Option<String>,
+        #[serde(default = "permanent")]
+        status: u16,
+    },
+}
+
+impl RedirectRule {
+    pub fn redirect(&self) -> Result<Redirect, String> {
+        match self {
+            RedirectRule::To(to) => Redirect::new(301, Some(to.clone())),
+            RedirectRule::With { to, status } => Redirect::new(*status, to.clone()),
+        }
+    }
+}
+
+/// Another URL a post used to be found at, as written in its `aliases`: either just the URL,
+/// for a 301, or a table like `{ url = "/posts/old.html", status = 302 }`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Alias {
+    Url(String),
+    With {
+        url: String,
+        #[serde(default = "permanent")]
+        status: u16,
+    },
+}
+
+impl Alias {
+    /// The URL of the alias and how it gets to `to`.
+    pub fn redirect(&self, to: &str) -> Result<(String, Redirect), String> {
+        let (url, status) = match self {
+            Alias::Url(url) => (url, 301),
+            Alias::With { url, status } => (url, *status),
+        };
+        Ok((url_path(url), Redirect::new(status, Some(to.to_owned()))?))
+    }
+}
+
+fn permanent() -> u16 {
+    301
+}
+
+/// Turns a URL as written, like `/old/` or `/old-post.html`, into the URL of the resource it
+/// is stored under. Directories get an `index.html` so they can be written out as stubs.
+pub fn url_path(url: &str) -> String {
+    let url = url.trim_start_matches('/');
+    let file = url.rsplit('/').next().unwrap_or_default();
+    if file.is_empty() {
+        format!("{}index.html", url)
+    } else if !file.contains('.') {
+        format!("{}/index.html", url)
+    } else {
+        url.to_owned()
+    }
+}
+
+/// The line for a redirect from `url` in a `_redirects` file, as read by Netlify, Cloudflare
+/// Pages and others.
+pub fn redirects_line(url: &str, redirect: &Redirect) -> String {
+    let from = url.strip_suffix("index.html").unwrap_or(url);
+    let to = redirect.location().unwrap_or("/not_found.html");
+    format!("/{} {} {}", from, to, redirect.status().as_u16())
+}
@@ -1,18 +1,64 @@
-use axum::http::header;
-use axum::response::IntoResponse;
+use arc_swap::ArcSwap;
+use axum::body::StreamBody;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use futures_util::future;
+use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
 use std::sync;
-use tracing::{debug, info};
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::{debug, error, info};
 
+use crate::compress::{self, Encoding};
+use crate::range;
+use crate::redirects::{self, Redirect};
 use crate::ResourceProcessor;
 
 pub const EXTENSIONS: &[&str] = &[
-    "css", "html", "jpg", "jpeg", "woff2", "liquid", "md", "markdown", "png", "svg", "webp",
+    "css", "html", "jpg", "jpeg", "woff2", "liquid", "md", "markdown", "png", "svg", "webp", "mp3",
+    "mp4", "ogg", "pdf", "webm",
 ];
 
+/// An error from processing a file, along with the file it came from.
+#[derive(Debug)]
+pub struct ProcessError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl ProcessError {
+    pub fn new(path: impl Into<PathBuf>, err: impl std::fmt::Display) -> Self {
+        Self {
+            path: path.into(),
+            message: err.to_string(),
+        }
+    }
+
+    /// Attributes `err` to `path`, unless it already names a more specific file.
+    fn wrap(path: &Path, err: Box<dyn Error>) -> Self {
+        match err.downcast::<ProcessError>() {
+            Ok(err) => *err,
+            Err(err) => Self::new(path, err),
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl Error for ProcessError {}
+
 #[derive(Clone, Default)]
 pub enum URLPath {
     #[default]
@@ -22,11 +68,36 @@ pub enum URLPath {
     Absolute(String),
 }
 
+/// A file too big to keep in memory, which is read from disk each time it is requested.
+#[derive(Clone, Debug)]
+pub struct OnDisk {
+    pub(crate) path: PathBuf,
+    pub(crate) len: u64,
+}
+
 #[derive(Clone, Default)]
 pub struct Resource {
     pub(crate) original_path: PathBuf,
     pub(crate) url_path: URLPath,
-    pub(crate) contents: Vec<u8>,
+    pub(crate) contents: Bytes,
+
+    /// Files, or directories of files, other than `original_path` that the resource was built
+    /// from. The resource is rebuilt whenever one of them changes.
+    pub(crate) dependencies: Vec<PathBuf>,
+
+    /// `contents` compressed ahead of time, if its content type is worth compressing
+    pub(crate) gzip: Option<Bytes>,
+    pub(crate) brotli: Option<Bytes>,
+
+    /// Where to redirect to, or that the resource is gone, instead of serving `contents`
+    pub(crate) redirect: Option<Redirect>,
+    /// Where to stream the resource from instead of serving `contents`
+    pub(crate) on_disk: Option<OnDisk>,
+
+    /// A hash of `contents`, sent as the `ETag`
+    pub(crate) hash: String,
+    /// When the newest of the files the resource was built from was modified
+    pub(crate) modified: Option<SystemTime>,
 }
 
 impl Resource {
@@ -58,6 +129,345 @@ impl Resource {
             .essence_str()
             .to_owned()
     }
+
+    /// Whether there is nothing to serve.
+    fn is_empty(&self) -> bool {
+        self.contents.is_empty() && self.redirect.is_none() && self.on_disk.is_none()
+    }
+
+    fn len(&self) -> u64 {
+        match &self.on_disk {
+            Some(on_disk) => on_disk.len,
+            None => self.contents.len() as u64,
+        }
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        self.redirect.is_some()
+    }
+
+    pub fn is_gone(&self) -> bool {
+        self.redirect == Some(Redirect::Gone)
+    }
+
+    /// Returns every file the resource was built from.
+    fn sources(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.original_path).chain(&self.dependencies)
+    }
+
+    /// Fills in everything worked out from the contents once they are final: the hash, the
+    /// modification time and the compressed versions. `modified` looks up the modification
+    /// time of each file the resource was built from.
+    fn finish(
+        &mut self,
+        modified: impl Fn(&Path) -> Option<SystemTime>,
+        level: compress::Level,
+    ) -> Result<(), Box<dyn Error>> {
+        self.modified = self.sources().filter_map(|p| modified(p)).max();
+
+        // Hashing a file on disk would mean reading all of it, so go by its size and age instead
+        if let Some(on_disk) = &self.on_disk {
+            let modified = self
+                .modified
+                .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            self.hash = format!("{:x}-{:x}", on_disk.len, modified.as_secs());
+            return Ok(());
+        }
+
+        self.hash = Sha256::digest(&self.contents)[..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        if !compress::compressible(&self.content_type()) {
+            return Ok(());
+        }
+
+        let smaller =
+            |buf: Vec<u8>| Some(Bytes::from(buf)).filter(|buf| buf.len() < self.contents.len());
+        self.gzip = smaller(compress::gzip(&self.contents, level)?);
+        self.brotli = smaller(compress::brotli(&self.contents, level)?);
+        Ok(())
+    }
+
+    fn body(&self, encoding: Encoding) -> Option<&Bytes> {
+        match encoding {
+            Encoding::Brotli => self.brotli.as_ref(),
+            Encoding::Gzip => self.gzip.as_ref(),
+            Encoding::Identity => Some(&self.contents),
+        }
+    }
+
+    fn encodings(&self) -> Vec<Encoding> {
+        [Encoding::Brotli, Encoding::Gzip]
+            .into_iter()
+            .filter(|e| self.body(*e).is_some())
+            .collect()
+    }
+
+    fn negotiate(&self, headers: &HeaderMap) -> Encoding {
+        let accept = headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        Encoding::negotiate(accept, &self.encodings())
+    }
+
+    /// The `ETag` for the contents in `encoding`. Each encoding is a different representation,
+    /// so needs its own tag.
+    fn etag(&self, encoding: Encoding) -> String {
+        match encoding {
+            Encoding::Identity => format!("\"{}\"", self.hash),
+            _ => format!("\"{}-{}\"", self.hash, encoding.name()),
+        }
+    }
+
+    /// Whether the copy the request already has, going by `If-None-Match` or
+    /// `If-Modified-Since`, is the same as `etag`.
+    fn not_modified(&self, headers: &HeaderMap, etag: &str) -> bool {
+        // If-Modified-Since is only used by clients that don't know the ETag
+        if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+            let Ok(if_none_match) = if_none_match.to_str() else {
+                return false;
+            };
+
+            return if_none_match
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*");
+        }
+
+        let (Some(modified), Some(since)) = (self.modified, headers.get(header::IF_MODIFIED_SINCE))
+        else {
+            return false;
+        };
+        let Some(since) = since
+            .to_str()
+            .ok()
+            .and_then(|s| httpdate::parse_http_date(s).ok())
+        else {
+            return false;
+        };
+
+        // HTTP dates only have whole seconds
+        let modified = modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let since = since
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        modified <= since
+    }
+
+    /// The ranges asked for by the request's `Range` header, or `None` if the whole resource
+    /// should be sent. `If-Range` makes the ranges only count if the resource hasn't changed,
+    /// since otherwise they'd be pieced together with parts of the old one.
+    fn ranges(&self, headers: &HeaderMap) -> Option<Vec<RangeInclusive<u64>>> {
+        let range = headers.get(header::RANGE)?.to_str().ok()?;
+
+        if let Some(if_range) = headers.get(header::IF_RANGE) {
+            let if_range = if_range.to_str().ok()?;
+            let unchanged = match httpdate::parse_http_date(if_range) {
+                // Dates only have whole seconds, so must match exactly to be trusted
+                Ok(date) => self.modified.is_some_and(|modified| {
+                    httpdate::fmt_http_date(modified) == httpdate::fmt_http_date(date)
+                }),
+                // Weak tags never match
+                Err(_) => if_range == self.etag(Encoding::Identity),
+            };
+            if !unchanged {
+                return None;
+            }
+        }
+
+        range::parse(range, self.len())
+    }
+
+    /// Responds with the contents compressed in the best way the request's `Accept-Encoding`
+    /// allows.
+    pub fn encoded_response(&self, headers: &HeaderMap) -> Response {
+        let encoding = self.negotiate(headers);
+        self.respond(encoding, None)
+    }
+
+    /// Responds with the contents in `encoding`, or just the `ranges` of them if there are any.
+    fn respond(&self, encoding: Encoding, ranges: Option<Vec<RangeInclusive<u64>>>) -> Response {
+        let source = match &self.on_disk {
+            Some(on_disk) => Source::Disk(on_disk.path.clone(), on_disk.len),
+            // Cloning the bytes only bumps a reference count
+            None => Source::Memory(self.body(encoding).unwrap_or(&self.contents).clone()),
+        };
+        let len = source.len();
+        let typ = self.content_type();
+
+        let mut res = match ranges.as_deref() {
+            None => match source {
+                Source::Memory(bytes) => ([(header::CONTENT_TYPE, typ)], bytes).into_response(),
+                source => (
+                    [
+                        (header::CONTENT_TYPE, typ),
+                        (header::CONTENT_LENGTH, len.to_string()),
+                    ],
+                    StreamBody::new(source.read(0, len)),
+                )
+                    .into_response(),
+            },
+            Some([]) => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", len))],
+            )
+                .into_response(),
+            Some([range]) => {
+                let part_len = range.end() - range.start() + 1;
+                (
+                    StatusCode::PARTIAL_CONTENT,
+                    [
+                        (header::CONTENT_TYPE, typ),
+                        (header::CONTENT_RANGE, range::content_range(range, len)),
+                        (header::CONTENT_LENGTH, part_len.to_string()),
+                    ],
+                    StreamBody::new(source.read(*range.start(), part_len)),
+                )
+                    .into_response()
+            }
+            Some(ranges) => {
+                let multipart = range::Multipart::new(typ, len);
+                let body_len = multipart.body_len(ranges);
+
+                let mut parts = Vec::new();
+                for (i, range) in ranges.iter().enumerate() {
+                    let header = Bytes::from(multipart.part_header(range, i == 0));
+                    parts.push(stream::once(future::ready(Ok(header))).boxed());
+                    let part_len = range.end() - range.start() + 1;
+                    parts.push(source.read(*range.start(), part_len));
+                }
+                let end = Bytes::from(multipart.end());
+                parts.push(stream::once(future::ready(Ok(end))).boxed());
+
+                (
+                    StatusCode::PARTIAL_CONTENT,
+                    [
+                        (header::CONTENT_TYPE, multipart.content_type()),
+                        (header::CONTENT_LENGTH, body_len.to_string()),
+                    ],
+                    StreamBody::new(stream::iter(parts).flatten()),
+                )
+                    .into_response()
+            }
+        };
+
+        let headers = res.headers_mut();
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if !self.encodings().is_empty() {
+            headers.insert(
+                header::VARY,
+                HeaderValue::from_static(header::ACCEPT_ENCODING.as_str()),
+            );
+        }
+        if encoding != Encoding::Identity {
+            headers.insert(
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.name()),
+            );
+        }
+        res
+    }
+
+    /// Like `encoded_response`, but also sends `ETag` and `Last-Modified`, answering with 304
+    /// Not Modified if the request's copy is still fresh, and honours `Range`.
+    pub fn response_for(&self, headers: &HeaderMap) -> Response {
+        // Where a redirect goes can change, like when a fingerprinted asset does, so it
+        // shouldn't be cached
+        if let Some(redirect) = &self.redirect {
+            let mut res = (
+                redirect.status(),
+                [(header::CACHE_CONTROL, "no-cache".to_owned())],
+            )
+                .into_response();
+            if let Some(location) = redirect
+                .location()
+                .and_then(|l| HeaderValue::from_str(l).ok())
+            {
+                res.headers_mut().insert(header::LOCATION, location);
+            }
+            return res;
+        }
+
+        // Ranges are always of the uncompressed contents, so that resuming a download doesn't
+        // depend on the encoding the first request got
+        let ranges = self.ranges(headers);
+        let encoding = match ranges {
+            Some(_) => Encoding::Identity,
+            None => self.negotiate(headers),
+        };
+        let etag = self.etag(encoding);
+        let not_modified = self.not_modified(headers, &etag);
+        let compressed = !self.encodings().is_empty();
+        let modified = self.modified;
+
+        let mut res = match not_modified {
+            true => StatusCode::NOT_MODIFIED.into_response(),
+            false => self.respond(encoding, ranges),
+        };
+
+        let headers = res.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(modified) = modified {
+            if let Ok(modified) = HeaderValue::from_str(&httpdate::fmt_http_date(modified)) {
+                headers.insert(header::LAST_MODIFIED, modified);
+            }
+        }
+        if not_modified && compressed {
+            headers.insert(
+                header::VARY,
+                HeaderValue::from_static(header::ACCEPT_ENCODING.as_str()),
+            );
+        }
+        res
+    }
+}
+
+/// Where the body of a response comes from.
+enum Source {
+    Memory(Bytes),
+    /// A file and its length, read as the body is sent
+    Disk(PathBuf, u64),
+}
+
+impl Source {
+    fn len(&self) -> u64 {
+        match self {
+            Source::Memory(bytes) => bytes.len() as u64,
+            Source::Disk(_, len) => *len,
+        }
+    }
+
+    /// Streams `len` bytes from `start`. Nothing is read until the stream is polled, so a `HEAD`
+    /// request, whose body is dropped, never touches the file.
+    fn read(&self, start: u64, len: u64) -> BoxStream<'static, std::io::Result<Bytes>> {
+        match self {
+            Source::Memory(bytes) => {
+                let part = bytes.slice(start as usize..(start + len) as usize);
+                stream::once(future::ready(Ok(part))).boxed()
+            }
+            Source::Disk(path, _) => {
+                let path = path.clone();
+                stream::once(async move {
+                    let mut file = tokio::fs::File::open(&path).await?;
+                    file.seek(std::io::SeekFrom::Start(start)).await?;
+                    Ok::<_, std::io::Error>(ReaderStream::new(file.take(len)))
+                })
+                .try_flatten()
+                .inspect_err(|e| error!(?e, "couldn't stream file"))
+                .boxed()
+            }
+        }
+    }
 }
 
 impl IntoResponse for Resource {
@@ -68,37 +478,353 @@ impl IntoResponse for Resource {
     }
 }
 
+#[derive(Default)]
+struct Contents {
+    resources: HashMap<String, sync::Arc<Resource>>,
+
+    /// The URLs produced from each source file
+    sources: HashMap<PathBuf, Vec<String>>,
+
+    /// The URLs produced by `ResourceProcessor::flush`
+    flushed: Vec<String>,
+
+    /// The URLs produced by `ResourceProcessor::summarise`
+    summaries: Vec<String>,
+
+    /// For each URL, the files it was built from
+    dependencies: HashMap<String, Vec<PathBuf>>,
+}
+
+/// Whether a resource built from `dependency` is affected by a change to `changed`. Either may be
+/// a directory.
+fn affected_by(dependency: &Path, changed: &Path) -> bool {
+    changed.starts_with(dependency) || dependency.starts_with(changed)
+}
+
+/// What produced a resource, which decides when it is thrown away.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Produced {
+    /// Processing its source file
+    Processed,
+    /// `ResourceProcessor::flush`
+    Flushed,
+    /// `ResourceProcessor::summarise`
+    Summarised,
+}
+
+impl Contents {
+    fn insert(&mut self, url: String, resource: Resource, produced: Produced) {
+        // A processor may produce nothing from a file, like a draft post, while still needing to
+        // look at it again when its dependencies change
+        if resource.is_empty() && resource.dependencies.is_empty() {
+            return;
+        }
+
+        let mut dependencies = vec![resource.original_path.clone()];
+        dependencies.extend(resource.dependencies.iter().cloned());
+        self.dependencies.insert(url.clone(), dependencies);
+
+        match produced {
+            Produced::Processed => self
+                .sources
+                .entry(resource.original_path.clone())
+                .or_default()
+                .push(url.clone()),
+            Produced::Flushed => self.flushed.push(url.clone()),
+            Produced::Summarised => self.summaries.push(url.clone()),
+        }
+
+        if resource.is_empty() {
+            return;
+        }
+
+        info!(url, content_length = resource.len(), "putting into store");
+
+        self.resources.insert(url, sync::Arc::new(resource));
+    }
+
+    /// Removes everything produced from `path`, or from files under `path` if it is a directory.
+    fn remove_source(&mut self, path: &Path) {
+        let sources: Vec<_> = self
+            .sources
+            .keys()
+            .filter(|source| source.starts_with(path))
+            .cloned()
+            .collect();
+
+        for source in sources {
+            debug!(?source, "removing resources for source");
+            for url in self.sources.remove(&source).unwrap_or_default() {
+                self.remove_url(&url);
+            }
+        }
+    }
+
+    fn remove_flushed(&mut self) {
+        for url in std::mem::take(&mut self.flushed) {
+            self.remove_url(&url);
+        }
+    }
+
+    fn remove_url(&mut self, url: &str) {
+        self.resources.remove(url);
+        self.dependencies.remove(url);
+    }
+
+    /// Returns the URL of each resource affected by `changed`, along with the source it was built
+    /// from.
+    fn dependents<'a>(
+        &'a self,
+        changed: &'a [PathBuf],
+    ) -> impl Iterator<Item = (&'a String, &'a PathBuf)> {
+        self.dependencies
+            .iter()
+            .filter(|(_, deps)| {
+                deps.iter()
+                    .any(|dep| changed.iter().any(|path| affected_by(dep, path)))
+            })
+            .map(|(url, deps)| (url, &deps[0]))
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct Store {
-    hm: sync::Arc<sync::Mutex<HashMap<String, Resource>>>,
+    /// Everything needed for rebuilding, which only rebuilds lock
+    hm: sync::Arc<sync::Mutex<Contents>>,
+
+    /// A snapshot of the resources in `hm`, published after each rebuild. Requests are served
+    /// from this so they never wait on a rebuild, or copy the resources.
+    served: sync::Arc<ArcSwap<HashMap<String, sync::Arc<Resource>>>>,
 }
 
 impl Store {
-    fn put(&mut self, path: String, resource: Resource) {
-        if resource.contents.is_empty() {
-            return;
-        }
+    /// Makes the resources in `hm` visible to `get`. The resources are shared, so this only
+    /// copies the map.
+    fn publish(&self, hm: &Contents) {
+        self.served.store(sync::Arc::new(hm.resources.clone()));
+    }
 
-        info!(
-            path,
-            content_length = resource.contents.len(),
-            "putting into store"
-        );
+    fn put(&mut self, url: String, resource: Resource) {
+        let mut hm = self.hm.lock().unwrap();
+        hm.insert(url, resource, Produced::Processed);
+    }
 
+    fn put_flushed(&mut self, url: String, resource: Resource) {
         let mut hm = self.hm.lock().unwrap();
-        hm.insert(path, resource);
+        hm.insert(url, resource, Produced::Flushed);
+    }
+
+    pub fn get(&self, path: &str) -> Option<sync::Arc<Resource>> {
+        self.served.load().get(path).cloned()
+    }
+
+    /// Returns the URLs of every resource that has to be rebuilt when `path` changes.
+    pub fn dependents(&self, path: &Path) -> Vec<String> {
+        let hm = self.hm.lock().unwrap();
+        let mut urls: Vec<_> = hm
+            .dependents(&[path.to_owned()])
+            .map(|(url, _)| url.clone())
+            .collect();
+        urls.sort();
+        urls
     }
 
-    pub fn get(&self, path: &str) -> Option<Resource> {
+    /// Returns every resource's URL along with the files it was built from.
+    pub fn dependencies(&self) -> BTreeMap<String, Vec<PathBuf>> {
         let hm = self.hm.lock().unwrap();
-        hm.get(path).cloned()
+        hm.dependencies
+            .iter()
+            .map(|(url, deps)| (url.clone(), deps.clone()))
+            .collect()
     }
 
     pub fn replace(&self, other: Store) {
         let mut other_handle = other.hm.lock().unwrap();
         let mut handle = self.hm.lock().unwrap();
-        std::mem::swap(&mut *handle, &mut *other_handle)
+        std::mem::swap(&mut *handle, &mut *other_handle);
+        self.publish(&handle);
+        other.publish(&other_handle);
+    }
+
+    /// Writes every resource to `output`, laid out by its URL.
+    pub fn write_to(&self, output: &Path) -> Result<(), Box<dyn Error>> {
+        let hm = self.hm.lock().unwrap();
+        let mut redirects = Vec::new();
+        for (url, resource) in hm.resources.iter() {
+            let path = output.join(url);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            // Static hosts are told about redirects in `_redirects`, with a page that redirects
+            // in the browser for those that don't read it
+            if let Some(redirect) = &resource.redirect {
+                redirects.push(redirects::redirects_line(url, redirect));
+                if let Some(stub) = redirect.stub().filter(|_| url.ends_with(".html")) {
+                    debug!(?path, "writing redirect stub");
+                    std::fs::write(&path, stub)?;
+                }
+                continue;
+            }
+
+            debug!(?path, "writing resource");
+            match &resource.on_disk {
+                Some(on_disk) => {
+                    std::fs::copy(&on_disk.path, &path)?;
+                }
+                None => std::fs::write(&path, &resource.contents)?,
+            }
+
+            // Written alongside the original for servers that send precompressed files
+            for encoding in resource.encodings() {
+                let mut compressed = path.clone().into_os_string();
+                compressed.push(".");
+                compressed.push(encoding.extension().unwrap());
+                std::fs::write(compressed, resource.body(encoding).unwrap())?;
+            }
+        }
+
+        if !redirects.is_empty() {
+            redirects.sort();
+            redirects.push(String::new());
+            std::fs::write(output.join("_redirects"), redirects.join("\n"))?;
+        }
+
+        info!(?output, count = hm.resources.len(), "wrote site");
+
+        Ok(())
+    }
+}
+
+fn has_wanted_extension(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => EXTENSIONS.iter().any(|wanted| *wanted == ext),
+        None => false,
+    }
+}
+
+fn process(
+    processor: &dyn ResourceProcessor,
+    path: &Path,
+    base: &Path,
+    finisher: &Finisher,
+) -> Result<(String, Resource), ProcessError> {
+    let mut resource = processor
+        .process(path)
+        .map_err(|e| ProcessError::wrap(path, e))?;
+    finisher
+        .finish(&mut resource)
+        .map_err(|e| ProcessError::new(path, e))?;
+    let url = resource.url(base).map_err(|e| ProcessError::new(path, e))?;
+    Ok((url, resource))
+}
+
+/// Flushes `processor`, returning the extra resources it produced along with their URLs.
+fn flush_processor(
+    processor: &dyn ResourceProcessor,
+    base: &Path,
+    finisher: &Finisher,
+) -> Result<Vec<(String, Resource)>, Box<dyn Error>> {
+    finish_all(processor.flush()?, base, finisher)
+}
+
+/// Replaces the resources from `ResourceProcessor::summarise` with ones describing the site as
+/// it is now.
+fn summarise(
+    hm: &mut Contents,
+    processors: &[&dyn ResourceProcessor],
+    base: &Path,
+    finisher: &Finisher,
+) -> Result<(), Box<dyn Error>> {
+    for url in std::mem::take(&mut hm.summaries) {
+        hm.remove_url(&url);
+    }
+
+    let mut summaries = Vec::new();
+    for processor in processors {
+        summaries.extend(finish_all(
+            processor.summarise(&hm.resources)?,
+            base,
+            finisher,
+        )?);
+    }
+
+    for (url, res) in summaries {
+        hm.insert(url, res, Produced::Summarised);
+    }
+
+    Ok(())
+}
+
+/// Finishes `resources` that weren't processed from a file of their own, returning them along
+/// with their URLs.
+fn finish_all(
+    resources: Vec<Resource>,
+    base: &Path,
+    finisher: &Finisher,
+) -> Result<Vec<(String, Resource)>, Box<dyn Error>> {
+    let resources = resources
+        .into_par_iter()
+        .map(|mut res| {
+            let original_path = res.original_path.clone();
+            finisher
+                .finish(&mut res)
+                .map_err(|e| ProcessError::new(&original_path, e))?;
+            let url = res
+                .url(base)
+                .map_err(|e| ProcessError::new(&original_path, e))?;
+            Ok((url, res))
+        })
+        .collect::<Result<_, ProcessError>>()?;
+    Ok(resources)
+}
+
+/// Finishes the resources of one build.
+struct Finisher {
+    level: compress::Level,
+    /// The modification times looked up so far, so each file is only looked at once. Resources
+    /// share the same, large, dependencies, like the posts and partials directories.
+    times: sync::Mutex<HashMap<PathBuf, Option<SystemTime>>>,
+}
+
+impl Finisher {
+    fn new(level: compress::Level) -> Self {
+        Self {
+            level,
+            times: sync::Mutex::default(),
+        }
     }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        if let Some(time) = self.times.lock().unwrap().get(path) {
+            return *time;
+        }
+
+        // Not holding the lock while walking directories lets other threads carry on
+        let time = modified(path);
+        self.times.lock().unwrap().insert(path.to_owned(), time);
+        time
+    }
+
+    fn finish(&self, resource: &mut Resource) -> Result<(), Box<dyn Error>> {
+        resource.finish(|path| self.modified(path), self.level)
+    }
+}
+
+/// Returns when `path`, or if it is a directory anything under it, was last modified. A
+/// directory's own modification time covers files being removed from it.
+fn modified(path: &Path) -> Option<SystemTime> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let mut newest = metadata.modified().ok()?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path).ok()?.flatten() {
+            if let Some(time) = modified(&entry.path()) {
+                newest = newest.max(time);
+            }
+        }
+    }
+
+    Some(newest)
 }
 
 fn walk(base: &Path, output: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
@@ -110,9 +836,8 @@ fn walk(base: &Path, output: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
             continue;
         }
 
-        match path.extension() {
-            Some(ext) if EXTENSIONS.iter().any(|wanted| *wanted == ext) => {}
-            _ => continue,
+        if !has_wanted_extension(&path) {
+            continue;
         }
 
         debug!(?path, "Found resource");
@@ -123,62 +848,212 @@ fn walk(base: &Path, output: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Returns the stages of `processors` in the order they should run.
+fn stages(processors: &[&dyn ResourceProcessor]) -> Vec<u32> {
+    let mut stages: Vec<_> = processors.iter().map(|p| p.stage()).collect();
+    stages.sort_unstable();
+    stages.dedup();
+    stages
+}
+
+/// Builds the whole site under `base`, compressing what's worth it at `level`.
 pub fn find_and_process<P: AsRef<Path>>(
     base: P,
     processors: &[&dyn ResourceProcessor],
+    level: compress::Level,
 ) -> Result<Store, Box<dyn Error>> {
     let start = std::time::Instant::now();
 
     info!(base=?base.as_ref(), "rebuilding");
 
     let mut paths = Vec::new();
-    let base = base.as_ref();
+    // Canonical, so the files found under it match those `process_changed` is told about
+    let base = &base.as_ref().canonicalize()?;
 
     walk(base, &mut paths)?;
 
-    let store = Store::default();
+    let mut store = Store::default();
+    let finisher = Finisher::new(level);
 
-    paths
-        .into_par_iter()
-        .try_for_each(|path| -> Result<(), String> {
-            let mut store = store.clone();
+    for stage in stages(processors) {
+        paths
+            .par_iter()
+            .try_for_each(|path| -> Result<(), ProcessError> {
+                let mut store = store.clone();
 
-            for processor in processors {
-                if !processor.matches(&path) {
-                    continue;
+                let Some(processor) = processors.iter().find(|p| p.matches(path)) else {
+                    return Ok(());
+                };
+                if processor.stage() != stage {
+                    return Ok(());
                 }
 
-                let resource = processor.process(&path).map_err(|e| e.to_string())?;
-                let url = resource.url(base).map_err(|e| e.to_string())?;
+                let (url, resource) = process(*processor, path, base, &finisher)?;
                 store.put(url, resource);
 
-                return Ok(());
+                Ok(())
+            })?;
+
+        for processor in processors.iter().filter(|p| p.stage() == stage) {
+            let resources = flush_processor(*processor, base, &finisher)?;
+            if resources.is_empty() {
+                continue;
             }
 
-            Ok(())
-        })?;
+            info!(
+                ?processor,
+                count = resources.len(),
+                "processor has extra resources"
+            );
+
+            for (url, res) in resources {
+                store.put_flushed(url, res);
+            }
+        }
+    }
 
-    let mut store = store;
+    let mut hm = store.hm.lock().unwrap();
+    summarise(&mut hm, processors, base, &finisher)?;
+    store.publish(&hm);
+    drop(hm);
 
-    for processor in processors {
-        let resources = processor.flush()?;
-        if resources.is_empty() {
-            continue;
+    info!(elapsed=?start.elapsed(), "rebuilding finished");
+
+    Ok(store)
+}
+
+/// Brings `store` up to date after the files in `changed` have been created, modified or
+/// deleted.
+///
+/// The changed files are reprocessed along with every resource that depends on them. Processors
+/// are only flushed again if one of the resources they produced in `flush` is affected.
+pub fn process_changed<P: AsRef<Path>>(
+    base: P,
+    processors: &[&dyn ResourceProcessor],
+    store: &Store,
+    changed: &[PathBuf],
+    level: compress::Level,
+) -> Result<(), Box<dyn Error>> {
+    let start = std::time::Instant::now();
+    // Changed files are reported with canonical paths, so they can only be found under a
+    // canonical base
+    let base = &base.as_ref().canonicalize()?;
+
+    info!(?changed, "rebuilding changed files");
+
+    // Files that are still there replace what the processors know about them once they have
+    // been processed, so if that fails the last good version is kept
+    for path in changed.iter().filter(|path| !path.exists()) {
+        for processor in processors {
+            processor.remove(path);
+        }
+    }
+
+    let (sources, flush) = {
+        let hm = store.hm.lock().unwrap();
+        let mut sources = changed.to_vec();
+        let mut flush = false;
+
+        for (url, source) in hm.dependents(changed) {
+            if hm.flushed.contains(url) {
+                flush = true;
+                continue;
+            }
+
+            if !sources.contains(source) {
+                sources.push(source.clone());
+            }
         }
 
-        info!(
-            ?processor,
-            count = resources.len(),
-            "processor has extra resources"
-        );
+        (sources, flush)
+    };
 
-        for res in resources {
-            let url = res.url(base)?;
-            store.put(url, res);
+    debug!(?sources, flush, "found sources to rebuild");
+
+    let finisher = Finisher::new(level);
+    let mut resources = Vec::new();
+    let mut flushed = Vec::new();
+    for stage in stages(processors) {
+        let processed: Vec<_> = sources
+            .par_iter()
+            .filter(|path| path.is_file() && has_wanted_extension(path))
+            .filter_map(|path| {
+                processors
+                    .iter()
+                    .find(|p| p.matches(path))
+                    .map(|processor| (path, processor))
+            })
+            .filter(|(_, processor)| processor.stage() == stage)
+            .map(|(path, processor)| process(*processor, path, base, &finisher))
+            .collect::<Result<_, _>>()?;
+        resources.extend(processed);
+
+        if flush {
+            for processor in processors.iter().filter(|p| p.stage() == stage) {
+                flushed.extend(flush_processor(*processor, base, &finisher)?);
+            }
         }
     }
 
-    info!(elapsed=?start.elapsed(), "rebuilding finished");
+    let mut hm = store.hm.lock().unwrap();
+    for path in &sources {
+        hm.remove_source(path);
+    }
 
-    Ok(store)
+    for (url, res) in resources {
+        hm.insert(url, res, Produced::Processed);
+    }
+
+    if flush {
+        hm.remove_flushed();
+        for (url, res) in flushed {
+            hm.insert(url, res, Produced::Flushed);
+        }
+    }
+
+    summarise(&mut hm, processors, base, &finisher)?;
+    store.publish(&hm);
+
+    info!(elapsed=?start.elapsed(), "rebuilding changed files finished");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::StaticProcessor;
+
+    /// `path`, which is absolute, relative to the current directory instead.
+    fn relative(path: &Path) -> PathBuf {
+        let cwd = std::env::current_dir().unwrap();
+        let mut relative = PathBuf::new();
+        for _ in cwd.components().skip(1) {
+            relative.push("..");
+        }
+        relative.join(path.strip_prefix("/").unwrap())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn process_changed_with_a_relative_base() {
+        let dir = std::env::temp_dir().join(format!("lumin-store-{:x}", rand::random::<u64>()));
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("style.css"), "a {}").unwrap();
+
+        let base = relative(&dir);
+        assert!(base.is_relative());
+        let processors: &[&dyn ResourceProcessor] = &[&StaticProcessor::default()];
+        let store = find_and_process(&base, processors, compress::Level::Fast).unwrap();
+
+        // The file watcher reports absolute paths
+        std::fs::write(dir.join("style.css"), "b {}").unwrap();
+        let changed = [dir.canonicalize().unwrap().join("style.css")];
+        let res = process_changed(&base, processors, &store, &changed, compress::Level::Fast);
+        let contents = store.get("style.css").map(|res| res.contents.clone());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        res.unwrap();
+        assert_eq!(contents.as_deref(), Some(&b"b {}"[..]));
+    }
 }
@@ -0,0 +1,293 @@
+use chrono::Utc;
+use liquid::{
+    model::{KStringCow, ObjectRender, ObjectSource, State, Value},
+    Object, ObjectView, ValueView,
+};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tracing::{debug, info};
+
+use crate::{
+    assets::{AssetUrl, Assets},
+    config::Config,
+    store::ProcessError,
+};
+
+const DEV_RELOAD: &str = r#"
+{% if development %}
+<script type="text/javascript">
+  const eventSrc = new EventSource('/sse/update');
+  eventSrc.onmessage = (_) => {
+    location.reload();
+  };
+  eventSrc.addEventListener('build-error', (event) => {
+    const error = JSON.parse(event.data);
+
+    let overlay = document.getElementById('lumin-error-overlay');
+    if (!overlay) {
+      overlay = document.createElement('div');
+      overlay.id = 'lumin-error-overlay';
+      overlay.style.cssText = 'position: fixed; inset: 0; z-index: 2147483647; overflow: auto;'
+        + 'padding: 2em; background: rgba(0, 0, 0, 0.85); color: #f8f8f2; font-family: monospace;';
+      document.body.appendChild(overlay);
+    }
+
+    const title = document.createElement('h2');
+    title.style.color = '#ff5555';
+    title.textContent = 'Build failed' + (error.path ? ' in ' + error.path : '');
+
+    const message = document.createElement('pre');
+    message.style.whiteSpace = 'pre-wrap';
+    message.textContent = error.message;
+
+    overlay.replaceChildren(title, message);
+  });
+</script>
+{% endif %}
+"#;
+
+fn create_parser(partials_dir: &Path, assets: &Assets) -> Result<liquid::Parser, Box<dyn Error>> {
+    let mut ims = liquid::partials::InMemorySource::new();
+
+    for entry in std::fs::read_dir(partials_dir)? {
+        let entry = entry?;
+        if entry.metadata()?.is_dir() {
+            continue;
+        }
+
+        let path = entry.path();
+        debug!(?path, "found partial");
+
+        let short_path = path.strip_prefix(partials_dir)?;
+        let buf = std::fs::read_to_string(&path)?;
+        ims.add(short_path.file_name().unwrap().to_string_lossy(), buf);
+    }
+
+    ims.add("dev_reload", DEV_RELOAD);
+
+    let partials = liquid::partials::EagerCompiler::new(ims);
+
+    Ok(liquid::ParserBuilder::new()
+        .stdlib()
+        .partials(partials)
+        .filter(AssetUrl::new(assets.clone()))
+        .build()?)
+}
+
+/// The variables a template is rendered with: its own, on top of the globals shared by every
+/// template. The globals hold every post, so this saves copying them for each render.
+#[derive(Debug)]
+struct Variables<'a> {
+    page: &'a Object,
+    globals: &'a Object,
+}
+
+impl ValueView for Variables<'_> {
+    fn as_debug(&self) -> &dyn std::fmt::Debug {
+        self
+    }
+
+    fn render(&self) -> liquid::model::DisplayCow<'_> {
+        liquid::model::DisplayCow::Owned(Box::new(ObjectRender::new(self)))
+    }
+
+    fn source(&self) -> liquid::model::DisplayCow<'_> {
+        liquid::model::DisplayCow::Owned(Box::new(ObjectSource::new(self)))
+    }
+
+    fn type_name(&self) -> &'static str {
+        "object"
+    }
+
+    fn query_state(&self, state: State) -> bool {
+        match state {
+            State::Truthy => true,
+            State::DefaultValue | State::Empty | State::Blank => self.size() == 0,
+        }
+    }
+
+    fn to_kstr(&self) -> KStringCow<'_> {
+        KStringCow::from_string(ObjectRender::new(self).to_string())
+    }
+
+    fn to_value(&self) -> Value {
+        let mut object = self.globals.clone();
+        object.extend(self.page.clone());
+        Value::Object(object)
+    }
+
+    fn as_object(&self) -> Option<&dyn ObjectView> {
+        Some(self)
+    }
+}
+
+impl ObjectView for Variables<'_> {
+    fn as_value(&self) -> &dyn ValueView {
+        self
+    }
+
+    fn size(&self) -> i64 {
+        self.keys().count() as i64
+    }
+
+    fn keys<'k>(&'k self) -> Box<dyn Iterator<Item = KStringCow<'k>> + 'k> {
+        Box::new(self.iter().map(|(k, _)| k))
+    }
+
+    fn values<'k>(&'k self) -> Box<dyn Iterator<Item = &'k dyn ValueView> + 'k> {
+        Box::new(self.iter().map(|(_, v)| v))
+    }
+
+    fn iter<'k>(&'k self) -> Box<dyn Iterator<Item = (KStringCow<'k>, &'k dyn ValueView)> + 'k> {
+        let globals = self
+            .globals
+            .iter()
+            .filter(|(k, _)| !self.page.contains_key(k.as_str()));
+        Box::new(
+            self.page
+                .iter()
+                .chain(globals)
+                .map(|(k, v)| (k.as_ref().into(), v.as_view())),
+        )
+    }
+
+    fn contains_key(&self, index: &str) -> bool {
+        self.page.contains_key(index) || self.globals.contains_key(index)
+    }
+
+    fn get<'s>(&'s self, index: &str) -> Option<&'s dyn ValueView> {
+        self.page
+            .get(index)
+            .or_else(|| self.globals.get(index))
+            .map(|v| v.as_view())
+    }
+}
+
+/// Variables given to every template.
+struct Globals {
+    /// `site` as configured in `lumin.toml`
+    site: Object,
+    development: bool,
+
+    /// Given to posts. They are rendered before every post has been collected, so `site` only
+    /// has the config and build time.
+    posts: Object,
+    /// Given to everything else, with `site` also holding all the posts and tags
+    pages: Object,
+}
+
+impl Globals {
+    fn new(site: Object, development: bool) -> Self {
+        let mut globals = Self {
+            site,
+            development,
+            posts: Object::new(),
+            pages: Object::new(),
+        };
+        globals.update(Value::array(vec![]), Value::array(vec![]));
+        globals
+    }
+
+    fn update(&mut self, posts: Value, tags: Value) {
+        let mut site = self.site.clone();
+        site.insert("build_time".into(), Value::scalar(Utc::now().to_rfc3339()));
+        self.posts = liquid::object!({"site": site, "development": self.development});
+
+        site.insert("posts".into(), posts);
+        site.insert("tags".into(), tags);
+        self.pages = liquid::object!({"site": site, "development": self.development});
+    }
+}
+
+/// A Liquid parser with the site's partials, along with the variables every template gets. These
+/// are shared between processors so that the partials can be reloaded when they change, and so
+/// pages can list the posts.
+#[derive(Clone)]
+pub struct Templates {
+    partials_dir: PathBuf,
+    parser: Arc<RwLock<Arc<liquid::Parser>>>,
+    globals: Arc<RwLock<Globals>>,
+    assets: Assets,
+}
+
+impl Templates {
+    pub fn new(config: &Config, assets: Assets, development: bool) -> Result<Self, Box<dyn Error>> {
+        let parser = create_parser(&config.partials_dir, &assets)?;
+        let site = liquid::to_object(&config.site)?;
+        Ok(Self {
+            partials_dir: config.partials_dir.clone(),
+            parser: Arc::new(RwLock::new(Arc::new(parser))),
+            globals: Arc::new(RwLock::new(Globals::new(site, development))),
+            assets,
+        })
+    }
+
+    pub fn partials_dir(&self) -> &Path {
+        &self.partials_dir
+    }
+
+    pub fn assets(&self) -> &Assets {
+        &self.assets
+    }
+
+    pub fn parser(&self) -> Arc<liquid::Parser> {
+        self.parser.read().unwrap().clone()
+    }
+
+    /// Re-reads the partials from disk. Templates parsed before this still use the old partials.
+    pub fn reload(&self) -> Result<(), Box<dyn Error>> {
+        info!(partials_dir=?self.partials_dir, "reloading partials");
+
+        let parser = create_parser(&self.partials_dir, &self.assets)
+            .map_err(|e| ProcessError::new(&self.partials_dir, e))?;
+        *self.parser.write().unwrap() = Arc::new(parser);
+        Ok(())
+    }
+
+    /// Sets the posts and tags in `site`, once every post has been collected. This also updates
+    /// the build time.
+    pub fn set_posts(&self, posts: Value, tags: Value) {
+        self.globals.write().unwrap().update(posts, tags);
+    }
+
+    /// Renders `template` with `page` and the global variables.
+    pub fn render(
+        &self,
+        template: &liquid::Template,
+        page: &Object,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let globals = self.globals.read().unwrap();
+        Self::render_with(template, page, &globals.pages)
+    }
+
+    /// Like `render`, but for posts, where `site` has no posts or tags.
+    pub fn render_post(
+        &self,
+        template: &liquid::Template,
+        page: &Object,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let globals = self.globals.read().unwrap();
+        Self::render_with(template, page, &globals.posts)
+    }
+
+    fn render_with(
+        template: &liquid::Template,
+        page: &Object,
+        globals: &Object,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        template.render_to(&mut buf, &Variables { page, globals })?;
+        Ok(buf)
+    }
+}
+
+impl std::fmt::Debug for Templates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Templates")
+            .field("partials_dir", &self.partials_dir)
+            .finish()
+    }
+}